@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_twisted::packet::DNSPacket;
+use rusty_twisted::parser::PacketParser;
+
+// Any input is either a packet or a ParseError, never a panic
+fuzz_target!(|data: &[u8]| {
+    let mut parser = PacketParser::from_bytes(data);
+    if DNSPacket::get_dns_packet(&mut parser).is_err() {
+        let _ = DNSPacket::format_error(&parser);
+    }
+});
@@ -0,0 +1,42 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::DNSPacket;
+use rusty_twisted::parser::PacketParser;
+use rusty_twisted::writer::PacketWriter;
+
+// Compressed names grow when written back without compression:
+// small inputs keep the written packet inside the writer buffer
+const MAX_INPUT: usize = 256;
+
+fn write(packet: &mut DNSPacket) -> Vec<u8> {
+    let mut writer = PacketWriter::new();
+    packet.write_dns_packet(&mut writer);
+    writer.buffer[..writer.position()].to_vec()
+}
+
+// A parsed packet written again parses to a packet that writes the same bytes
+fuzz_target!(|data: &[u8]| {
+    if data.len() > MAX_INPUT {
+        return;
+    }
+    let mut packet = match DNSPacket::get_dns_packet(&mut PacketParser::from_bytes(data)) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let written = write(&mut packet);
+
+    let mut reparsed = DNSPacket::get_dns_packet(&mut PacketParser::from_bytes(&written))
+        .expect("a written packet parses again");
+    assert_eq!(reparsed.header, packet.header);
+    assert_eq!(reparsed.questions, packet.questions);
+    assert_eq!(write(&mut reparsed), written);
+
+    // Names keep every byte of their labels through their text form
+    for question in &packet.questions {
+        let text = question.qname.to_string();
+        let name: Name = text.parse().expect("the text form of a name parses");
+        assert_eq!(name.labels(), question.qname.labels());
+    }
+});
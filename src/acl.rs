@@ -0,0 +1,66 @@
+use std::net::IpAddr;
+
+use crate::name::Name;
+
+// Check if addr is allowed by an access list of addresses and CIDR ranges
+// Entries are like "192.0.2.1", "10.0.0.0/8" or "2001:db8::/32"
+// Invalid entries never match
+pub fn is_allowed(addr: IpAddr, acl: &[String]) -> bool {
+    acl.iter().any(|entry| match parse_cidr(entry) {
+        Some((network, prefix_len)) => in_network(addr, network, prefix_len),
+        None => {
+            println!("Ignoring invalid ACL entry {:?}", entry);
+            false
+        }
+    })
+}
+
+// Check if a request signed with the key key_name is allowed by a list of key names
+// Key names are compared ignoring case
+pub fn is_key_allowed(key_name: Option<&Name>, keys: &[Name]) -> bool {
+    match key_name {
+        Some(key_name) => keys.contains(key_name),
+        None => false,
+    }
+}
+
+// Parse "addr" or "addr/prefix_len"; a single address is a full length prefix
+pub fn parse_cidr(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix_len) = match entry.trim().split_once('/') {
+        Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+        None => (entry.trim(), None),
+    };
+
+    let addr = addr.parse::<IpAddr>().ok()?;
+    let max_len = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix_len = match prefix_len {
+        Some(x) => x.parse::<u8>().ok()?,
+        None => max_len,
+    };
+    if prefix_len > max_len {
+        return None;
+    }
+
+    Some((addr, prefix_len))
+}
+
+// Compare the first prefix_len bits of the two addresses
+// IPv4-mapped IPv6 addresses match IPv4 networks
+fn in_network(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    let addr = match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+        IpAddr::V4(_) => addr,
+    };
+
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
@@ -1,8 +1,14 @@
 
 use std::{collections::{BTreeMap, HashMap, HashSet}, hash::{Hash, Hasher}, sync::{Arc, RwLock}};
 use chrono::{DateTime, Duration, Local};
+use rand::Rng;
 use packet::DNSRecord;
 use crate::packet::{self, DNSPacket, QueryType, RCode};
+use crate::dnssec::find_denial;
+use crate::name::Name;
+
+// Most NSEC and NSEC3 records kept for one zone
+const MAX_DENIAL_RECORDS: usize = 10000;
 
 pub enum CacheState {
     PositiveCache,
@@ -13,6 +19,8 @@ pub enum CacheState {
 pub struct RecordEntry {
     pub record: DNSRecord,
     pub timestamp: DateTime<Local>,
+    // The answer it came from was validated with DNSSEC
+    pub authed_data: bool,
 }
 
 impl PartialEq<RecordEntry> for RecordEntry {
@@ -30,28 +38,40 @@ impl Hash for RecordEntry {
     }
 }
 
-#[derive(Debug)]
+// Kind of negative answer (RFC 2308)
+// NXDOMAIN: the name does not exist at all
+// NODATA: the name exists, but has no records of the queried type
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NegativeType {
+    NxDomain,
+    NoData,
+}
+
+#[derive(Clone, Debug)]
 pub enum RecordSet {
     NoRecords {
         qtype: QueryType,
+        negative_type: NegativeType,
+        soa: Option<DNSRecord>,
         ttl: u32,
         timestamp: DateTime<Local>,
+        authed_data: bool,
     },
     Records {
         qtype: QueryType,
         records: HashSet <RecordEntry>,
     },
 }
-#[derive(Default, Debug)]
+#[derive(Default, Clone, Debug)]
 pub struct DomainEntry {
-    pub domain: String,
+    pub domain: Name,
     pub record_types: HashMap<QueryType, RecordSet>,
     pub hits: u32,
     pub updates: u32,
 }
 
 impl DomainEntry {
-    pub fn new(domain: String) -> DomainEntry {
+    pub fn new(domain: Name) -> DomainEntry {
         DomainEntry {
             domain: domain,
             record_types: HashMap::new(),
@@ -61,23 +81,83 @@ impl DomainEntry {
     }
 
     pub fn store_nxdomain(&mut self, qtype: QueryType, ttl: u32) {
+        self.store_negative(qtype, NegativeType::NxDomain, None, ttl, false);
+    }
+
+    pub fn store_negative(&mut self, qtype: QueryType, negative_type: NegativeType, soa: Option<DNSRecord>, ttl: u32, authed_data: bool) {
         self.updates += 1;
 
         let new_set = RecordSet::NoRecords {
-            qtype: qtype,
-            ttl: ttl,
+            qtype,
+            negative_type,
+            soa,
+            ttl,
             timestamp: Local::now(),
+            authed_data,
         };
 
         self.record_types.insert(qtype, new_set);
     }
 
-    pub fn store_record(&mut self, rec: &DNSRecord) {
+    // Get the negative set answering for qtype, if one is still valid
+    // An NXDOMAIN entry stored for any type covers every type of the name
+    fn get_negative_set(&self, qtype: QueryType) -> Option<&RecordSet> {
+        let now = Local::now();
+        let is_valid = |set: &RecordSet| match *set {
+            RecordSet::NoRecords { ttl, timestamp, .. } => timestamp + Duration::seconds(ttl as i64) >= now,
+            RecordSet::Records { .. } => false,
+        };
+
+        if let Some(set) = self.record_types.get(&qtype) {
+            if is_valid(set) {
+                return Some(set);
+            }
+        }
+
+        self.record_types.values().find(|set| {
+            matches!(set, RecordSet::NoRecords { negative_type: NegativeType::NxDomain, .. }) && is_valid(set)
+        })
+    }
+
+    // Fill the authority section of a negative answer with the cached SOA
+    // The SOA TTL is set to the remaining lifetime of the negative entry
+    pub fn fill_negative_result(&self, qtype: QueryType, packet: &mut DNSPacket) {
+        if let Some(&RecordSet::NoRecords { negative_type, ref soa, ttl, timestamp, authed_data, .. }) = self.get_negative_set(qtype) {
+            packet.header.rcode = match negative_type {
+                NegativeType::NxDomain => RCode::NXDOMAIN,
+                NegativeType::NoData => RCode::NOERROR,
+            };
+            packet.header.authed_data = authed_data;
+
+            if let Some(DNSRecord::SOA { domain, mname, rname, serial, refresh, retry, expire, minimum, .. }) = soa.clone() {
+                let elapsed = (Local::now() - timestamp).num_seconds().max(0) as u32;
+                packet.authorities.push(DNSRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    ttl: ttl.saturating_sub(elapsed),
+                });
+            }
+        }
+    }
+
+    pub fn store_record(&mut self, rec: &DNSRecord, authed_data: bool) {
         self.updates += 1;
 
+        // The name exists, so any cached NXDOMAIN is no longer valid
+        self.record_types.retain(|_, set| {
+            !matches!(set, RecordSet::NoRecords { negative_type: NegativeType::NxDomain, .. })
+        });
+
         let entry = RecordEntry {
             record: rec.clone(),
             timestamp: Local::now(),
+            authed_data,
         };
 
         if let Some(&mut RecordSet::Records {
@@ -123,34 +203,33 @@ impl DomainEntry {
 
                 if valid_count > 0 {
                     CacheState::PositiveCache
+                } else if self.get_negative_set(qtype).is_some() {
+                    CacheState::NegativeCache
                 } else {
                     CacheState::NotCached
                 }
             }
 
-            Some(&RecordSet::NoRecords { ttl, timestamp, ..}) => {
-                let now = Local::now();
-                let ttl_offset = Duration::seconds(ttl as i64);
-                let expires = timestamp + ttl_offset;
-
-                if expires < now {
-                    CacheState::NotCached
-                } else {
+            Some(&RecordSet::NoRecords { .. }) | None => {
+                if self.get_negative_set(qtype).is_some() {
                     CacheState::NegativeCache
+                } else {
+                    CacheState::NotCached
                 }
             }
-            None => CacheState::NotCached,
         }
     }
 
-    pub fn fill_query_result(&self, qtype: QueryType, result_vec: &mut Vec<DNSRecord>) {
+    // Returns true if all the records added were validated
+    pub fn fill_query_result(&self, qtype: QueryType, result_vec: &mut Vec<DNSRecord>) -> bool {
         let now = Local::now();
 
         let current_set = match self.record_types.get(&qtype) {
             Some(x) => x,
-            None => return,
+            None => return false,
         }; 
 
+        let mut authed_data = true;
         if let RecordSet::Records { ref records, ..} = *current_set {
             for entry in records {
                 let ttl_offset = Duration::seconds(entry.record.clone().get_ttl() as i64);
@@ -161,53 +240,103 @@ impl DomainEntry {
 
                 if entry.record.clone().get_query_type() == qtype {
                     result_vec.push(entry.record.clone());
+                    authed_data &= entry.authed_data;
                 }
             }
         }
+        authed_data
+    }
+}
+
+// A validated NSEC or NSEC3 record with its signatures
+#[derive(Clone, Debug)]
+pub struct DenialEntry {
+    pub record: DNSRecord,
+    pub rrsigs: Vec<DNSRecord>,
+    pub ttl: u32,
+    pub timestamp: DateTime<Local>,
+}
+
+impl DenialEntry {
+    fn remaining_ttl(&self, now: DateTime<Local>) -> i64 {
+        (self.timestamp + Duration::seconds(self.ttl as i64) - now).num_seconds()
     }
 }
 
+// The validated denial records of one zone, by owner and type
+// The SOA goes in the authority section of the answers made from them
+#[derive(Clone, Debug)]
+pub struct ZoneDenials {
+    pub soa: DNSRecord,
+    pub soa_rrsigs: Vec<DNSRecord>,
+    pub records: BTreeMap<(Name, u16), DenialEntry>,
+}
+
+// Negative answers made from the cached denial records
+#[derive(Default, Clone, Copy, Debug)]
+pub struct DenialStats {
+    pub nxdomain: u64,
+    pub nodata: u64,
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct Cache {
-    pub domain_entries: BTreeMap<String, Arc<DomainEntry>>,
+    // Names are compared without their case, so every spelling shares one entry
+    pub domain_entries: BTreeMap<Name, Arc<DomainEntry>>,
+    pub denials: BTreeMap<Name, ZoneDenials>,
+    pub denial_stats: DenialStats,
 }
 
 impl Cache {
     pub fn new() -> Cache {
         Cache {
             domain_entries: BTreeMap::new(),
+            denials: BTreeMap::new(),
+            denial_stats: DenialStats::default(),
         }
     }
 
-    fn get_cache_state(&mut self, qname: &str, qtype: QueryType) -> CacheState {
+    fn get_cache_state(&mut self, qname: &Name, qtype: QueryType) -> CacheState {
         match self.domain_entries.get(qname) {
             Some(x) => x.get_cache_state(qtype),
             None => CacheState::NotCached,
         }
     }
 
-    fn fill_query_result(&mut self, qname: &str, qtype: QueryType, result_vec: &mut Vec<DNSRecord>, increment_stats: bool) {
+    fn fill_query_result(&mut self, qname: &Name, qtype: QueryType, result_vec: &mut Vec<DNSRecord>, increment_stats: bool) -> bool {
         if let Some(domain_entry) = self.domain_entries.get_mut(qname).and_then(Arc::get_mut) {
             if increment_stats {
                 domain_entry.hits += 1
             }
 
-            domain_entry.fill_query_result(qtype, result_vec);
+            return domain_entry.fill_query_result(qtype, result_vec);
         }
+        false
     }
 
-    pub fn lookup(&mut self, qname: &str, qtype: QueryType) -> Option<DNSPacket> {
+    pub fn lookup(&mut self, qname: &Name, qtype: QueryType) -> Option<DNSPacket> {
         match self.get_cache_state(qname, qtype) {
             CacheState::PositiveCache => {
                 let mut qr =DNSPacket::new();
-                self.fill_query_result(qname, qtype, &mut qr.answers, true);
+                qr.header.authed_data = self.fill_query_result(qname, qtype, &mut qr.answers, true);
                 self.fill_query_result(qname, QueryType::NS, &mut qr.authorities, false);
 
+                // Signatures of the answer, for clients that validate it themselves
+                if qtype != QueryType::RRSIG {
+                    let mut rrsigs = Vec::new();
+                    self.fill_query_result(qname, QueryType::RRSIG, &mut rrsigs, false);
+                    qr.answers.extend(rrsigs.into_iter().filter(|record| {
+                        matches!(record, DNSRecord::RRSIG { type_covered, .. } if *type_covered == qtype.to_num())
+                    }));
+                }
+
                 Some(qr)
             }
             CacheState::NegativeCache => {
                 let mut qr = DNSPacket::new();
-                qr.header.rcode = RCode::NXDOMAIN;
+                if let Some(domain_entry) = self.domain_entries.get(qname) {
+                    domain_entry.fill_negative_result(qtype, &mut qr);
+                }
 
                 Some(qr)
             }
@@ -215,34 +344,187 @@ impl Cache {
         }
     }
 
-    pub fn store(&mut self, records: &[DNSRecord]) {
+    // authed_data tells if the records were validated with DNSSEC
+    pub fn store(&mut self, records: &[DNSRecord], authed_data: bool) {
         for rec in records {
             let domain = match rec.clone().get_domain() {
                 Some(x) => x,
                 None => continue,
             };
 
-            // if let Some(ref mut rs) = self.domain_entries.get_mut(&domain).and_then(Arc::get_mut) {
-            //     rs.store_record(rec);
-            //     self.domain_entries.insert(domain.clone(), Arc::new(rs));
-            // }
-
-            let mut rs = DomainEntry::new(domain.clone());
-            rs.store_record(rec);
-            self.domain_entries.insert(domain.clone(), Arc::new(rs));
+            // Positive records replace any negative entry for their type
+            let rs = self.domain_entries.entry(domain.clone())
+                .or_insert_with(|| Arc::new(DomainEntry::new(domain.clone())));
+            Arc::make_mut(rs).store_record(rec, authed_data);
         }
     }
-    pub fn store_nxdomain(&mut self, qname: &str, qtype: QueryType, ttl: u32) {
+    pub fn store_nxdomain(&mut self, qname: &Name, qtype: QueryType, ttl: u32) {
         if let Some(ref mut rs) = self.domain_entries.get_mut(qname).and_then(Arc::get_mut) {
             rs.store_nxdomain(qtype, ttl);
             return;
         }
-        let mut rs = DomainEntry::new(qname.to_string());
+        let mut rs = DomainEntry::new(qname.clone());
         rs.store_nxdomain(qtype, ttl);
-        self.domain_entries.insert(qname.to_string(), Arc::new(rs));
+        self.domain_entries.insert(qname.clone(), Arc::new(rs));
 
     }   
+
+    // Cache a negative answer (RFC 2308)
+    // The negative TTL is min(SOA TTL, SOA MINIMUM)
+    pub fn store_negative(&mut self, qname: &Name, qtype: QueryType, negative_type: NegativeType, soa: &DNSRecord, authed_data: bool) {
+        let ttl = match *soa {
+            DNSRecord::SOA { ttl, minimum, .. } => ttl.min(minimum),
+            _ => return,
+        };
+
+        let rs = self.domain_entries.entry(qname.clone())
+            .or_insert_with(|| Arc::new(DomainEntry::new(qname.clone())));
+        Arc::make_mut(rs).store_negative(qtype, negative_type, Some(soa.clone()), ttl, authed_data);
+    }
+
+    // Keep the NSEC and NSEC3 records of a validated negative answer (RFC 8198)
+    // Their TTL is capped by the negative TTL of the zone (RFC 8198 section 5.4)
+    pub fn store_denial(&mut self, authorities: &[DNSRecord]) {
+        let soa = match authorities.iter().find(|record| matches!(record, DNSRecord::SOA { .. })) {
+            Some(x) => x,
+            None => return,
+        };
+        let max_ttl = match *soa {
+            DNSRecord::SOA { ttl, minimum, .. } => ttl.min(minimum),
+            _ => return,
+        };
+        let zone = soa.clone().get_domain().unwrap_or_default();
+
+        let owner_of = |record: &DNSRecord| record.clone().get_domain().unwrap_or_default();
+        let rrsigs_for = |owner: &Name, qtype: QueryType| -> Vec<DNSRecord> {
+            authorities.iter()
+                .filter(|record| matches!(record, DNSRecord::RRSIG { type_covered, .. } if *type_covered == qtype.to_num()))
+                .filter(|record| owner_of(record) == *owner)
+                .cloned()
+                .collect()
+        };
+
+        let now = Local::now();
+        let zone_denials = self.denials.entry(zone.clone()).or_insert_with(|| ZoneDenials {
+            soa: soa.clone(),
+            soa_rrsigs: Vec::new(),
+            records: BTreeMap::new(),
+        });
+        zone_denials.soa = soa.clone();
+        zone_denials.soa_rrsigs = rrsigs_for(&zone, QueryType::SOA);
+        zone_denials.records.retain(|_, entry| entry.remaining_ttl(now) > 0);
+
+        for record in authorities {
+            let qtype = record.clone().get_query_type();
+            if qtype != QueryType::NSEC && qtype != QueryType::NSEC3 {
+                continue;
+            }
+            let owner = owner_of(record);
+            if !owner.is_subdomain_of(&zone) {
+                continue;
+            }
+            let key = (owner.clone(), qtype.to_num());
+            if zone_denials.records.len() >= MAX_DENIAL_RECORDS && !zone_denials.records.contains_key(&key) {
+                break;
+            }
+
+            zone_denials.records.insert(key, DenialEntry {
+                record: record.clone(),
+                rrsigs: rrsigs_for(&owner, qtype),
+                ttl: record.clone().get_ttl().min(max_ttl),
+                timestamp: now,
+            });
+        }
+    }
+
+    // Answer NXDOMAIN or NODATA from the cached denial records of the closest zone
+    // The answer carries the SOA and the proof with their signatures
+    pub fn lookup_denial(&mut self, qname: &Name, qtype: QueryType) -> Option<DNSPacket> {
+        let zone = self.denials.keys()
+            .filter(|zone| qname.is_subdomain_of(zone))
+            .max_by_key(|zone| zone.label_count())?
+            .clone();
+        let zone_denials = self.denials.get(&zone)?;
+
+        let now = Local::now();
+        let live: Vec<&DenialEntry> = zone_denials.records.values()
+            .filter(|entry| entry.remaining_ttl(now) > 0)
+            .collect();
+        let records: Vec<DNSRecord> = live.iter().map(|entry| entry.record.clone()).collect();
+        let (rcode, proof) = find_denial(&records, qname, qtype.to_num())?;
+
+        // Every record of the answer expires with the first proof record
+        let proof_entries: Vec<&&DenialEntry> = live.iter().filter(|entry| proof.contains(&entry.record)).collect();
+        let ttl = proof_entries.iter().map(|entry| entry.remaining_ttl(now)).min().unwrap_or(0).max(0) as u32;
+        let with_ttl = |record: &DNSRecord| {
+            let mut record = record.clone();
+            record.set_ttl(ttl);
+            record
+        };
+
+        let mut packet = DNSPacket::new();
+        packet.header.rcode = rcode;
+        packet.header.authed_data = true;
+        packet.authorities.push(with_ttl(&zone_denials.soa));
+        packet.authorities.extend(zone_denials.soa_rrsigs.iter().map(with_ttl));
+        for entry in proof_entries {
+            packet.authorities.push(with_ttl(&entry.record));
+            packet.authorities.extend(entry.rrsigs.iter().map(with_ttl));
+        }
+
+        match rcode {
+            RCode::NXDOMAIN => self.denial_stats.nxdomain += 1,
+            _ => self.denial_stats.nodata += 1,
+        }
+
+        Some(packet)
+    }
+}
+// Order SRV records for serving (RFC 2782)
+// Lowest priority first; inside a priority the order is a weighted random selection
+// Records of other types keep their place after the SRV records
+pub fn order_srv_records(records: &mut Vec<DNSRecord>) {
+    let (mut srv_records, others): (Vec<DNSRecord>, Vec<DNSRecord>) = records.drain(..)
+        .partition(|record| matches!(record, DNSRecord::SRV { .. }));
+
+    let srv_priority = |record: &DNSRecord| match *record {
+        DNSRecord::SRV { priority, .. } => priority,
+        _ => 0,
+    };
+    let srv_weight = |record: &DNSRecord| match *record {
+        DNSRecord::SRV { weight, .. } => weight as u32,
+        _ => 0,
+    };
+
+    srv_records.sort_by_key(srv_priority);
+
+    let mut rng = rand::thread_rng();
+    while !srv_records.is_empty() {
+        // Records sharing the lowest remaining priority
+        let priority = srv_priority(&srv_records[0]);
+        let group_len = srv_records.iter().take_while(|record| srv_priority(record) == priority).count();
+        let mut group: Vec<DNSRecord> = srv_records.drain(..group_len).collect();
+
+        // Zero weight records go first so they have a small chance of being picked
+        group.sort_by_key(|record| srv_weight(record) != 0);
+
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(srv_weight).sum();
+            let selected = rng.gen_range(0..=total);
+
+            let mut running_sum = 0;
+            let index = group.iter().position(|record| {
+                running_sum += srv_weight(record);
+                running_sum >= selected
+            }).unwrap_or(0);
+
+            records.push(group.remove(index));
+        }
+    }
+
+    records.extend(others);
 }
+
 #[derive(Default, Debug)]
 pub struct SynchronizedCache {
     pub cache: RwLock<Cache>,
@@ -275,7 +557,7 @@ impl SynchronizedCache {
         list
     }
 
-    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DNSPacket> {
+    pub fn lookup(&self, qname: &Name, qtype: QueryType) -> Option<DNSPacket> {
         let mut cache = match self.cache.write() {
             Ok(x) => x,
             Err(_) => return None,
@@ -284,15 +566,40 @@ impl SynchronizedCache {
         cache.lookup(qname, qtype)
     }
 
-    pub fn store(&self, records: &[DNSRecord]) {
+    pub fn store(&self, records: &[DNSRecord], authed_data: bool) {
         let mut cache = self.cache.write().unwrap();
 
-        cache.store(records);
+        cache.store(records, authed_data);
     }
 
-    pub fn store_nxdomain(&self, qname: &str, qtype: QueryType, ttl: u32) {
+    pub fn store_nxdomain(&self, qname: &Name, qtype: QueryType, ttl: u32) {
         let mut cache = self.cache.write().unwrap();
 
         cache.store_nxdomain(qname, qtype, ttl);
     }
+
+    pub fn store_negative(&self, qname: &Name, qtype: QueryType, negative_type: NegativeType, soa: &DNSRecord, authed_data: bool) {
+        let mut cache = self.cache.write().unwrap();
+
+        cache.store_negative(qname, qtype, negative_type, soa, authed_data);
+    }
+
+    pub fn store_denial(&self, authorities: &[DNSRecord]) {
+        let mut cache = self.cache.write().unwrap();
+
+        cache.store_denial(authorities);
+    }
+
+    pub fn lookup_denial(&self, qname: &Name, qtype: QueryType) -> Option<DNSPacket> {
+        let mut cache = match self.cache.write() {
+            Ok(x) => x,
+            Err(_) => return None,
+        };
+
+        cache.lookup_denial(qname, qtype)
+    }
+
+    pub fn denial_stats(&self) -> DenialStats {
+        self.cache.read().unwrap().denial_stats
+    }
 }
\ No newline at end of file
@@ -0,0 +1,1114 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::digest;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::name::{escape_label, Name};
+use crate::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+use crate::writer::PacketWriter;
+
+// DNSSEC record types (RFC 4034, RFC 5155)
+pub const DS: u16 = 43;
+pub const RRSIG: u16 = 46;
+pub const NSEC: u16 = 47;
+pub const DNSKEY: u16 = 48;
+pub const NSEC3: u16 = 50;
+pub const NSEC3PARAM: u16 = 51;
+// Type of the NSEC records of compact denial, for names that do not exist (RFC 9824 section 2)
+pub const NXNAME: u16 = 128;
+
+// Signature algorithms we can verify
+pub const RSASHA256: u8 = 8;
+pub const ECDSAP256SHA256: u8 = 13;
+pub const ED25519: u8 = 15;
+
+// DNSKEY flags
+const ZONE_KEY: u16 = 0x0100;
+const REVOKED: u16 = 0x0080;
+
+// NSEC3 opt-out flag
+const OPT_OUT: u8 = 0x01;
+// More NSEC3 iterations than this are treated as insecure (RFC 9276 section 3.2)
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+// Root zone KSK-2017 and KSK-2024, as DS records
+fn default_trust_anchors() -> Vec<String> {
+    vec![
+        "20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D".to_string(),
+        "38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16".to_string(),
+    ]
+}
+
+// Settings of the validating resolver
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct DnssecConfig {
+    // Validate the answers of the recursive resolver
+    #[serde(default)]
+    pub validate: bool,
+    // DS records of the root zone keys, like "20326 8 2 E06D44B8..."
+    #[serde(default = "default_trust_anchors")]
+    pub trust_anchors: Vec<String>,
+}
+
+impl Default for DnssecConfig {
+    fn default() -> Self {
+        DnssecConfig {
+            validate: false,
+            trust_anchors: default_trust_anchors(),
+        }
+    }
+}
+
+// Security status of an answer (RFC 4033 section 5)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Security {
+    Secure,
+    Insecure,
+    Bogus,
+    Indeterminate,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dnskey {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ds {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rrsig {
+    pub type_covered: u16,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: Name,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nsec {
+    pub next: Name,
+    pub types: Vec<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nsec3 {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    pub next_hashed: Vec<u8>,
+    pub types: Vec<u16>,
+}
+
+impl Dnskey {
+    pub fn from_record(record: &DNSRecord) -> Option<Dnskey> {
+        match record {
+            DNSRecord::DNSKEY { flags, protocol, algorithm, public_key, .. } => Some(Dnskey {
+                flags: *flags,
+                protocol: *protocol,
+                algorithm: *algorithm,
+                public_key: public_key.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_record(&self, owner: &Name, ttl: u32) -> DNSRecord {
+        DNSRecord::DNSKEY {
+            domain: owner.clone(),
+            flags: self.flags,
+            protocol: self.protocol,
+            algorithm: self.algorithm,
+            public_key: self.public_key.clone(),
+            ttl,
+        }
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut data = self.flags.to_be_bytes().to_vec();
+        data.push(self.protocol);
+        data.push(self.algorithm);
+        data.extend_from_slice(&self.public_key);
+        data
+    }
+
+    // Key tag (RFC 4034 appendix B)
+    pub fn key_tag(&self) -> u16 {
+        let mut sum: u32 = 0;
+        for (i, byte) in self.to_rdata().iter().enumerate() {
+            sum += if i % 2 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+        }
+        sum += (sum >> 16) & 0xFFFF;
+        (sum & 0xFFFF) as u16
+    }
+
+    // Revoked keys and keys that are not zone keys never sign zone data
+    fn is_zone_key(&self) -> bool {
+        self.flags & ZONE_KEY != 0 && self.flags & REVOKED == 0 && self.protocol == 3
+    }
+}
+
+impl Ds {
+    pub fn from_record(record: &DNSRecord) -> Option<Ds> {
+        match record {
+            DNSRecord::DS { key_tag, algorithm, digest_type, digest, .. } => Some(Ds {
+                key_tag: *key_tag,
+                algorithm: *algorithm,
+                digest_type: *digest_type,
+                digest: digest.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    // Parse a trust anchor written like a DS record: key tag, algorithm, digest type, hex digest
+    pub fn from_text(text: &str) -> Option<Ds> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() < 4 {
+            return None;
+        }
+        let hex: String = fields[3..].concat();
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        let digest = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        Some(Ds {
+            key_tag: fields[0].parse().ok()?,
+            algorithm: fields[1].parse().ok()?,
+            digest_type: fields[2].parse().ok()?,
+            digest,
+        })
+    }
+
+    // Check if the DS is the digest of the DNSKEY of zone
+    pub fn matches(&self, zone: &Name, key: &Dnskey) -> bool {
+        if self.key_tag != key.key_tag() || self.algorithm != key.algorithm {
+            return false;
+        }
+        let mut data = canonical_name(zone);
+        data.extend_from_slice(&key.to_rdata());
+
+        match ds_digest(self.digest_type, &data) {
+            Some(digest) => digest == self.digest,
+            None => false,
+        }
+    }
+
+    fn is_supported(&self) -> bool {
+        is_supported_algorithm(self.algorithm) && ds_digest(self.digest_type, &[]).is_some()
+    }
+}
+
+// Digest of a DS record: SHA-1, SHA-256 or SHA-384
+pub fn ds_digest(digest_type: u8, data: &[u8]) -> Option<Vec<u8>> {
+    let algorithm = match digest_type {
+        1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        2 => &digest::SHA256,
+        4 => &digest::SHA384,
+        _ => return None,
+    };
+    Some(digest::digest(algorithm, data).as_ref().to_vec())
+}
+
+impl Rrsig {
+    pub fn from_record(record: &DNSRecord) -> Option<Rrsig> {
+        match record {
+            DNSRecord::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature, .. } => Some(Rrsig {
+                type_covered: *type_covered,
+                algorithm: *algorithm,
+                labels: *labels,
+                original_ttl: *original_ttl,
+                expiration: *expiration,
+                inception: *inception,
+                key_tag: *key_tag,
+                signer: signer.clone(),
+                signature: signature.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_record(&self, owner: &Name, ttl: u32) -> DNSRecord {
+        DNSRecord::RRSIG {
+            domain: owner.clone(),
+            type_covered: self.type_covered,
+            algorithm: self.algorithm,
+            labels: self.labels,
+            original_ttl: self.original_ttl,
+            expiration: self.expiration,
+            inception: self.inception,
+            key_tag: self.key_tag,
+            signer: self.signer.clone(),
+            signature: self.signature.clone(),
+            ttl,
+        }
+    }
+
+    // The RRSIG RDATA without the signature, with the signer name in canonical form
+    pub fn signed_fields(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.type_covered.to_be_bytes());
+        data.push(self.algorithm);
+        data.push(self.labels);
+        data.extend_from_slice(&self.original_ttl.to_be_bytes());
+        data.extend_from_slice(&self.expiration.to_be_bytes());
+        data.extend_from_slice(&self.inception.to_be_bytes());
+        data.extend_from_slice(&self.key_tag.to_be_bytes());
+        data.extend_from_slice(&canonical_name(&self.signer));
+        data
+    }
+
+    // Inception and expiration are compared in serial number arithmetic (RFC 4034 section 3.1.5)
+    fn is_current(&self, now: u32) -> bool {
+        (now.wrapping_sub(self.inception) as i32) >= 0 && (self.expiration.wrapping_sub(now) as i32) >= 0
+    }
+}
+
+impl Nsec {
+    pub fn from_record(record: &DNSRecord) -> Option<Nsec> {
+        match record {
+            DNSRecord::NSEC { next, types, .. } => Some(Nsec {
+                next: next.clone(),
+                types: types.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Nsec3 {
+    pub fn from_record(record: &DNSRecord) -> Option<Nsec3> {
+        match record {
+            DNSRecord::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types, .. } => Some(Nsec3 {
+                hash_algorithm: *hash_algorithm,
+                flags: *flags,
+                iterations: *iterations,
+                salt: salt.clone(),
+                next_hashed: next_hashed.clone(),
+                types: types.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_opt_out(&self) -> bool {
+        self.flags & OPT_OUT != 0
+    }
+}
+
+// Name in wire format, lowercase and uncompressed
+pub fn canonical_name(name: &Name) -> Vec<u8> {
+    let mut writer = PacketWriter::new();
+    writer.write_qname(&name.to_lowercase());
+    writer.buffer[..writer.position()].to_vec()
+}
+
+// The wildcard right below a name
+fn wildcard_of(name: &Name) -> Option<Name> {
+    name.child(b"*").ok()
+}
+
+// RDATA in canonical form: names lowercase and uncompressed (RFC 4034 section 6.2)
+// The next name of NSEC records keeps its case (RFC 6840 section 5.1)
+pub fn canonical_rdata(record: &DNSRecord) -> Vec<u8> {
+    let mut record = record.clone();
+    match record {
+        DNSRecord::NS { ref mut host, .. }
+        | DNSRecord::CNAME { ref mut host, .. }
+        | DNSRecord::PTR { ref mut host, .. }
+        | DNSRecord::MX { ref mut host, .. }
+        | DNSRecord::SRV { ref mut host, .. } => *host = host.to_lowercase(),
+        DNSRecord::SOA { ref mut mname, ref mut rname, .. } => {
+            *mname = mname.to_lowercase();
+            *rname = rname.to_lowercase();
+        }
+        DNSRecord::RRSIG { ref mut signer, .. } => *signer = signer.to_lowercase(),
+        _ => {}
+    }
+    record.get_rdata()
+}
+
+// Canonical order of the records of an RRset, without duplicates (RFC 4034 section 6.3)
+pub fn canonical_sort(rrset: &mut Vec<DNSRecord>) {
+    rrset.sort_by_cached_key(canonical_rdata);
+    rrset.dedup_by(|a, b| canonical_rdata(a) == canonical_rdata(b));
+}
+
+fn record_type(record: &DNSRecord) -> u16 {
+    record.clone().get_query_type().to_num()
+}
+
+fn record_owner(record: &DNSRecord) -> Name {
+    record.clone().get_domain().unwrap_or_default()
+}
+
+// The RRSIG records of a section, with their owner
+pub fn get_rrsigs(records: &[DNSRecord]) -> Vec<(Name, Rrsig)> {
+    records.iter()
+        .filter_map(|record| Some((record_owner(record), Rrsig::from_record(record)?)))
+        .collect()
+}
+
+// Group the records of a section in RRsets: same owner and type
+// The RRSIG records are not part of the RRsets they cover
+pub fn get_rrsets(records: &[DNSRecord]) -> Vec<(Name, u16, Vec<DNSRecord>)> {
+    let mut rrsets: Vec<(Name, u16, Vec<DNSRecord>)> = Vec::new();
+    for record in records {
+        let (owner, qtype) = (record_owner(record), record_type(record));
+        if qtype == RRSIG || qtype == QueryType::OPT.to_num() {
+            continue;
+        }
+        match rrsets.iter_mut().find(|(o, t, _)| *o == owner && *t == qtype) {
+            Some((_, _, rrset)) => rrset.push(record.clone()),
+            None => rrsets.push((owner, qtype, vec![record.clone()])),
+        }
+    }
+    rrsets
+}
+
+// The data covered by a signature (RFC 4034 section 3.1.8.1)
+// Wildcard answers are signed with the wildcard owner name
+pub fn signed_data(rrsig: &Rrsig, owner: &Name, rrset: &[DNSRecord]) -> Vec<u8> {
+    let signed_owner = if (rrsig.labels as usize) < owner.label_count() {
+        wildcard_of(&owner.suffix(rrsig.labels as usize)).unwrap_or_else(|| owner.clone())
+    } else {
+        owner.clone()
+    };
+
+    let mut rrset = rrset.to_vec();
+    canonical_sort(&mut rrset);
+
+    let mut data = rrsig.signed_fields();
+    for rdata in rrset.iter().map(canonical_rdata) {
+        data.extend_from_slice(&canonical_name(&signed_owner));
+        data.extend_from_slice(&rrsig.type_covered.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&rrsig.original_ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(&rdata);
+    }
+    data
+}
+
+pub fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, RSASHA256 | ECDSAP256SHA256 | ED25519)
+}
+
+// Check a signature with a DNSKEY
+pub fn verify_signature(key: &Dnskey, data: &[u8], sig: &[u8]) -> bool {
+    match key.algorithm {
+        RSASHA256 => {
+            // Exponent length, exponent and modulus (RFC 3110 section 2)
+            let key = &key.public_key;
+            let (exponent_len, start) = match key.first() {
+                Some(0) if key.len() > 3 => (u16::from_be_bytes([key[1], key[2]]) as usize, 3),
+                Some(x) => (*x as usize, 1),
+                None => return false,
+            };
+            if key.len() <= start + exponent_len {
+                return false;
+            }
+            let components = RsaPublicKeyComponents {
+                n: &key[start + exponent_len..],
+                e: &key[start..start + exponent_len],
+            };
+            components.verify(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, data, sig).is_ok()
+        }
+        ECDSAP256SHA256 => {
+            // The key is the uncompressed point without its 0x04 prefix (RFC 6605 section 4)
+            let mut point = vec![4];
+            point.extend_from_slice(&key.public_key);
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(data, sig).is_ok()
+        }
+        ED25519 => UnparsedPublicKey::new(&signature::ED25519, &key.public_key).verify(data, sig).is_ok(),
+        _ => false,
+    }
+}
+
+// Hashed owner name of NSEC3 (RFC 5155 section 5)
+pub fn nsec3_hash(name: &Name, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut data = canonical_name(name);
+    data.extend_from_slice(salt);
+    let mut hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data).as_ref().to_vec();
+    for _ in 0..iterations {
+        let mut data = hash.clone();
+        data.extend_from_slice(salt);
+        hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data).as_ref().to_vec();
+    }
+    hash
+}
+
+// Base32 with the extended hex alphabet, without padding (RFC 4648 section 7)
+// The encoding keeps the order of the hashes
+pub fn base32hex(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1F) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1F) as usize] as char);
+    }
+    out
+}
+
+// Check if name is between owner and next in canonical order
+// The last NSEC of a zone wraps around to the apex
+fn nsec_covers(owner: &Name, next: &Name, name: &Name) -> bool {
+    let after_owner = owner.cmp(name) == Ordering::Less;
+    let before_next = name.cmp(next) == Ordering::Less;
+    if owner.cmp(next) == Ordering::Less {
+        after_owner && before_next
+    } else {
+        after_owner || before_next
+    }
+}
+
+// Same on the hashes of NSEC3, in their base32hex form
+fn nsec3_covers(owner_hash: &str, next_hash: &str, hash: &str) -> bool {
+    if owner_hash < next_hash {
+        owner_hash < hash && hash < next_hash
+    } else {
+        owner_hash < hash || hash < next_hash
+    }
+}
+
+// NS without SOA: the owner is a zone cut, seen from the parent side
+fn is_delegation(types: &[u16]) -> bool {
+    types.contains(&QueryType::NS.to_num()) && !types.contains(&QueryType::SOA.to_num())
+}
+
+// Result of a proof of non-existence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Proof {
+    Proven,
+    // An opt-out NSEC3 covers the name: there may be an unsigned delegation
+    OptOut,
+    // Too many NSEC3 iterations or an unknown hash: nothing can be proven
+    Unsupported,
+    Missing,
+}
+
+// The validated NSEC and NSEC3 records of an answer
+struct Denial {
+    nsecs: Vec<(Name, Nsec)>,
+    nsec3s: Vec<(Name, Nsec3)>,
+}
+
+impl Denial {
+    fn from_records(records: &[DNSRecord]) -> Denial {
+        Denial {
+            nsecs: records.iter()
+                .filter_map(|record| Some((record_owner(record), Nsec::from_record(record)?)))
+                .collect(),
+            nsec3s: records.iter()
+                .filter_map(|record| Some((record_owner(record), Nsec3::from_record(record)?)))
+                .collect(),
+        }
+    }
+
+    fn nsec_matching(&self, name: &Name) -> Option<&Nsec> {
+        self.nsecs.iter().find(|(owner, _)| owner == name).map(|(_, nsec)| nsec)
+    }
+
+    // The NSEC of a delegation in the parent zone says nothing about the names
+    // of the child zone (RFC 6840 section 4.1)
+    fn nsec_covering(&self, name: &Name) -> Option<&(Name, Nsec)> {
+        self.nsecs.iter().find(|(owner, nsec)| {
+            nsec_covers(owner, &nsec.next, name) && !(name.is_subdomain_of(owner) && is_delegation(&nsec.types))
+        })
+    }
+
+    // Closest encloser of a name covered by an NSEC: the longest ancestor shared
+    // with the owner or the next name of the NSEC
+    fn nsec_closest_encloser(name: &Name, owner: &Name, next: &Name) -> Name {
+        let common = |other: &Name| {
+            name.labels().iter().rev()
+                .zip(other.labels().iter().rev())
+                .take_while(|(x, y)| x.eq_ignore_ascii_case(y))
+                .count()
+        };
+        name.suffix(common(owner).max(common(next)))
+    }
+
+    // The NSEC3 records with the hash of their owner and their zone
+    fn usable_nsec3s(&self) -> Result<Vec<(String, Name, &Nsec3)>, Proof> {
+        let mut nsec3s = Vec::new();
+        for (owner, nsec3) in &self.nsec3s {
+            if nsec3.hash_algorithm != 1 || nsec3.iterations > MAX_NSEC3_ITERATIONS {
+                return Err(Proof::Unsupported);
+            }
+            let (hash, zone) = match (owner.labels().first(), owner.parent()) {
+                (Some(hash), Some(zone)) => (escape_label(&hash.to_ascii_lowercase()), zone),
+                _ => continue,
+            };
+            nsec3s.push((hash, zone, nsec3));
+        }
+        Ok(nsec3s)
+    }
+
+    fn nsec3_matching<'a>(nsec3s: &[(String, Name, &'a Nsec3)], name: &Name) -> Option<&'a Nsec3> {
+        nsec3s.iter()
+            .find(|(hash, zone, nsec3)| name.is_subdomain_of(zone) && *hash == base32hex(&nsec3_hash(name, &nsec3.salt, nsec3.iterations)))
+            .map(|(_, _, nsec3)| *nsec3)
+    }
+
+    fn nsec3_covering<'a>(nsec3s: &[(String, Name, &'a Nsec3)], name: &Name) -> Option<&'a Nsec3> {
+        nsec3s.iter()
+            .find(|(hash, zone, nsec3)| {
+                name.is_subdomain_of(zone)
+                    && nsec3_covers(hash, &base32hex(&nsec3.next_hashed), &base32hex(&nsec3_hash(name, &nsec3.salt, nsec3.iterations)))
+            })
+            .map(|(_, _, nsec3)| *nsec3)
+    }
+
+    // Closest encloser proof (RFC 5155 section 8.3)
+    // Returns the closest encloser and the NSEC3 covering the next closer name
+    fn nsec3_closest_encloser<'a>(nsec3s: &[(String, Name, &'a Nsec3)], name: &Name) -> Option<(Name, &'a Nsec3)> {
+        for n in (0..name.label_count()).rev() {
+            let encloser = name.suffix(n);
+            let nsec3 = match Denial::nsec3_matching(nsec3s, &encloser) {
+                Some(x) => x,
+                None => continue,
+            };
+            if is_delegation(&nsec3.types) {
+                return None;
+            }
+            let next_closer = name.suffix(n + 1);
+            return Denial::nsec3_covering(nsec3s, &next_closer).map(|nsec3| (encloser, nsec3));
+        }
+        None
+    }
+
+    // The name does not exist, and no wildcard could have matched it
+    fn proves_nxdomain(&self, name: &Name) -> Proof {
+        if let Some((owner, nsec)) = self.nsec_covering(name) {
+            let encloser = Denial::nsec_closest_encloser(name, owner, &nsec.next);
+            if wildcard_of(&encloser).is_some_and(|wildcard| self.nsec_covering(&wildcard).is_some()) {
+                return Proof::Proven;
+            }
+        }
+
+        let nsec3s = match self.usable_nsec3s() {
+            Ok(x) => x,
+            Err(proof) => return proof,
+        };
+        if let Some((encloser, nsec3)) = Denial::nsec3_closest_encloser(&nsec3s, name) {
+            if wildcard_of(&encloser).is_some_and(|wildcard| Denial::nsec3_covering(&nsec3s, &wildcard).is_some()) {
+                return if nsec3.is_opt_out() { Proof::OptOut } else { Proof::Proven };
+            }
+        }
+        Proof::Missing
+    }
+
+    // The name exists but has no records of type qtype
+    // At a zone cut only the DS records belong to the parent zone
+    fn proves_nodata(&self, name: &Name, qtype: u16) -> Proof {
+        let cname = QueryType::CNAME.to_num();
+        let usable = |types: &[u16]| qtype == DS || !is_delegation(types);
+        if let Some(nsec) = self.nsec_matching(name).filter(|nsec| usable(&nsec.types)) {
+            if !nsec.types.contains(&qtype) && !nsec.types.contains(&cname) {
+                return Proof::Proven;
+            }
+        }
+        // Wildcard NODATA: the name is covered and the wildcard has no such records
+        if let Some((owner, nsec)) = self.nsec_covering(name) {
+            // Empty non-terminal: the next name is below the name (RFC 4035 section 3.1.3.2)
+            if nsec.next.is_subdomain_of(name) {
+                return Proof::Proven;
+            }
+            let encloser = Denial::nsec_closest_encloser(name, owner, &nsec.next);
+            if let Some(wildcard) = wildcard_of(&encloser).and_then(|wildcard| self.nsec_matching(&wildcard)) {
+                if !wildcard.types.contains(&qtype) && !wildcard.types.contains(&cname) {
+                    return Proof::Proven;
+                }
+            }
+        }
+
+        let nsec3s = match self.usable_nsec3s() {
+            Ok(x) => x,
+            Err(proof) => return proof,
+        };
+        if let Some(nsec3) = Denial::nsec3_matching(&nsec3s, name).filter(|nsec3| usable(&nsec3.types)) {
+            if !nsec3.types.contains(&qtype) && !nsec3.types.contains(&cname) {
+                return Proof::Proven;
+            }
+        }
+        if let Some((encloser, nsec3)) = Denial::nsec3_closest_encloser(&nsec3s, name) {
+            // No DS below an opt-out span (RFC 5155 section 8.6)
+            if qtype == DS && nsec3.is_opt_out() {
+                return Proof::OptOut;
+            }
+            if let Some(wildcard) = wildcard_of(&encloser).and_then(|wildcard| Denial::nsec3_matching(&nsec3s, &wildcard)) {
+                if !wildcard.types.contains(&qtype) && !wildcard.types.contains(&cname) {
+                    return Proof::Proven;
+                }
+            }
+        }
+        Proof::Missing
+    }
+
+    // A wildcard answer needs a proof that the name itself does not exist
+    // With compact denial it is the NSEC of the name with the NXNAME type
+    fn proves_wildcard(&self, name: &Name, encloser_labels: usize) -> Proof {
+        if self.nsec_covering(name).is_some() {
+            return Proof::Proven;
+        }
+        if self.nsec_matching(name).is_some_and(|nsec| nsec.types.contains(&NXNAME)) {
+            return Proof::Proven;
+        }
+        let nsec3s = match self.usable_nsec3s() {
+            Ok(x) => x,
+            Err(proof) => return proof,
+        };
+        match Denial::nsec3_covering(&nsec3s, &name.suffix(encloser_labels + 1)) {
+            Some(nsec3) if nsec3.is_opt_out() => Proof::OptOut,
+            Some(_) => Proof::Proven,
+            None => Proof::Missing,
+        }
+    }
+}
+
+// What the parent zone says about the delegation to a name
+#[derive(Clone, Debug)]
+enum DsState {
+    // A signed delegation with these DS records
+    Secure(Vec<Ds>),
+    // An unsigned delegation, or an unsigned parent
+    Insecure,
+    // The name is not a zone cut
+    NoCut,
+    Bogus,
+}
+
+// Keys of a zone, once the chain of trust is checked
+#[derive(Clone, Debug)]
+enum ZoneKeys {
+    Secure(Vec<Dnskey>),
+    Insecure,
+    Bogus,
+}
+
+// Builds the chains of trust from the root trust anchors down to the answers
+// Queries go through lookup, which has to set the DO bit
+pub struct Validator<'a> {
+    anchors: Vec<Ds>,
+    lookup: &'a dyn Fn(&Name, QueryType) -> DNSPacket,
+    now: u32,
+    ds_states: HashMap<Name, DsState>,
+    keys: HashMap<Name, ZoneKeys>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(config: &DnssecConfig, lookup: &'a dyn Fn(&Name, QueryType) -> DNSPacket) -> Validator<'a> {
+        let anchors = config.trust_anchors.iter()
+            .filter_map(|anchor| {
+                let ds = Ds::from_text(anchor);
+                if ds.is_none() {
+                    println!("Ignoring invalid trust anchor {:?}", anchor);
+                }
+                ds
+            })
+            .collect();
+
+        Validator {
+            anchors,
+            lookup,
+            now: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32,
+            ds_states: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    // Validate the answer to a query for qname and qtype
+    pub fn validate(&mut self, qname: &Name, qtype: QueryType, response: &DNSPacket) -> Security {
+        if response.header.rcode != RCode::NOERROR && response.header.rcode != RCode::NXDOMAIN {
+            return Security::Indeterminate;
+        }
+        let qtype = qtype.to_num();
+        let answer_sigs = get_rrsigs(&response.answers);
+        let authority_sigs = get_rrsigs(&response.authorities);
+
+        let mut security = Security::Secure;
+        let mut wildcards = Vec::new();
+        for (owner, rrset_type, rrset) in get_rrsets(&response.answers) {
+            let status = self.verify_rrset(&owner, rrset_type, &rrset, &answer_sigs);
+            security = combine(security, status);
+
+            // Signatures with less labels than the owner come from a wildcard
+            if let Some((_, rrsig)) = answer_sigs.iter().find(|(o, sig)| *o == owner && sig.type_covered == rrset_type) {
+                if (rrsig.labels as usize) < owner.label_count() {
+                    wildcards.push((owner.clone(), rrsig.labels as usize));
+                }
+            }
+        }
+
+        // A CNAME chain without the records asked for ends in a negative answer
+        let positive = response.answers.iter().any(|record| record_type(record) == qtype)
+            || (qtype == 255 && !response.answers.is_empty()); // ANY
+        if positive && wildcards.is_empty() {
+            return security;
+        }
+
+        // Negative answers and wildcards are proven by the signed NSEC or NSEC3 records
+        let mut denial_records = Vec::new();
+        for (owner, rrset_type, rrset) in get_rrsets(&response.authorities) {
+            if rrset_type != QueryType::SOA.to_num() && rrset_type != NSEC && rrset_type != NSEC3 {
+                continue;
+            }
+            let status = self.verify_rrset(&owner, rrset_type, &rrset, &authority_sigs);
+            security = combine(security, status);
+            if status == Security::Secure {
+                denial_records.extend(rrset);
+            }
+        }
+        if !positive && response.authorities.is_empty() {
+            security = combine(security, self.unsigned_security(qname));
+        }
+        if security != Security::Secure {
+            return security;
+        }
+
+        let denial = Denial::from_records(&denial_records);
+        let mut proofs = Vec::new();
+        for (owner, encloser_labels) in &wildcards {
+            proofs.push(denial.proves_wildcard(owner, *encloser_labels));
+        }
+        if !positive {
+            // The answer may be the end of a CNAME chain
+            let name = response.answers.iter().rev()
+                .find_map(|record| match record {
+                    DNSRecord::CNAME { host, .. } => Some(host.clone()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| qname.clone());
+            proofs.push(if response.header.rcode == RCode::NXDOMAIN {
+                denial.proves_nxdomain(&name)
+            } else {
+                denial.proves_nodata(&name, qtype)
+            });
+        }
+
+        for proof in proofs {
+            match proof {
+                Proof::Proven => {}
+                Proof::OptOut | Proof::Unsupported => return Security::Insecure,
+                Proof::Missing => {
+                    println!("No proof of non-existence for {}", qname);
+                    return Security::Bogus;
+                }
+            }
+        }
+        Security::Secure
+    }
+
+    // Check the signatures of an RRset with the keys of the zone that signed it
+    fn verify_rrset(&mut self, owner: &Name, rrset_type: u16, rrset: &[DNSRecord], rrsigs: &[(Name, Rrsig)]) -> Security {
+        let sigs: Vec<&Rrsig> = rrsigs.iter()
+            .filter(|(sig_owner, rrsig)| sig_owner == owner && rrsig.type_covered == rrset_type)
+            .map(|(_, rrsig)| rrsig)
+            .collect();
+        if sigs.is_empty() {
+            return self.unsigned_security(owner);
+        }
+
+        let mut insecure = false;
+        for rrsig in sigs {
+            if !owner.is_subdomain_of(&rrsig.signer) || rrsig.labels as usize > owner.label_count() {
+                continue;
+            }
+            match self.zone_keys(&rrsig.signer) {
+                ZoneKeys::Secure(keys) => {
+                    if self.verify_rrsig(owner, rrset, rrsig, &keys) {
+                        return Security::Secure;
+                    }
+                }
+                ZoneKeys::Insecure => insecure = true,
+                ZoneKeys::Bogus => {}
+            }
+        }
+
+        if insecure {
+            Security::Insecure
+        } else {
+            println!("No valid signature for {} type {}", owner, rrset_type);
+            Security::Bogus
+        }
+    }
+
+    fn verify_rrsig(&self, owner: &Name, rrset: &[DNSRecord], rrsig: &Rrsig, keys: &[Dnskey]) -> bool {
+        if !rrsig.is_current(self.now) {
+            println!("Signature of {} by {} is expired or not yet valid", owner, rrsig.signer);
+            return false;
+        }
+        let data = signed_data(rrsig, owner, rrset);
+        keys.iter()
+            .filter(|key| key.is_zone_key() && key.algorithm == rrsig.algorithm && key.key_tag() == rrsig.key_tag)
+            .any(|key| verify_signature(key, &data, &rrsig.signature))
+    }
+
+    // Unsigned data is only acceptable below an unsigned delegation:
+    // walk down from the root to find it
+    fn unsigned_security(&mut self, name: &Name) -> Security {
+        for n in 1..=name.label_count() {
+            let ancestor = name.suffix(n);
+            match self.ds_state(&ancestor) {
+                DsState::Secure(_) => match self.zone_keys(&ancestor) {
+                    ZoneKeys::Secure(_) => {}
+                    ZoneKeys::Insecure => return Security::Insecure,
+                    ZoneKeys::Bogus => return Security::Bogus,
+                },
+                DsState::Insecure => return Security::Insecure,
+                DsState::NoCut => {}
+                DsState::Bogus => return Security::Bogus,
+            }
+        }
+
+        // The zone is signed, the data should be too
+        println!("Missing signatures for {}", name);
+        Security::Bogus
+    }
+
+    // Ask the parent for the DS records of a name and validate the answer
+    fn ds_state(&mut self, name: &Name) -> DsState {
+        if let Some(state) = self.ds_states.get(name) {
+            return state.clone();
+        }
+        // Guards against loops while the chain is built
+        self.ds_states.insert(name.clone(), DsState::Bogus);
+        let state = self.query_ds_state(name);
+        self.ds_states.insert(name.clone(), state.clone());
+        state
+    }
+
+    fn query_ds_state(&mut self, name: &Name) -> DsState {
+        let response = (self.lookup)(name, QueryType::DS);
+        if response.header.rcode != RCode::NOERROR && response.header.rcode != RCode::NXDOMAIN {
+            println!("DS lookup of {} failed with {:?}", name, response.header.rcode);
+            return DsState::Bogus;
+        }
+
+        let ds_records: Vec<DNSRecord> = response.answers.iter()
+            .filter(|record| record_type(record) == DS && record_owner(record) == *name)
+            .cloned()
+            .collect();
+        if !ds_records.is_empty() {
+            let sigs = get_rrsigs(&response.answers);
+            return match self.verify_parent_rrset(name, DS, &ds_records, &sigs) {
+                Security::Secure => {
+                    let ds: Vec<Ds> = ds_records.iter()
+                        .filter_map(Ds::from_record)
+                        .filter(|ds| ds.is_supported())
+                        .collect();
+                    // Only unknown algorithms: the zone is treated as unsigned (RFC 4035 section 5.2)
+                    if ds.is_empty() { DsState::Insecure } else { DsState::Secure(ds) }
+                }
+                Security::Insecure => DsState::Insecure,
+                _ => DsState::Bogus,
+            };
+        }
+
+        // No DS: the NSEC or NSEC3 of the parent tell if this is an unsigned delegation
+        let sigs = get_rrsigs(&response.authorities);
+        let mut denial_records = Vec::new();
+        for (_, rrset_type, rrset) in get_rrsets(&response.authorities) {
+            if rrset_type != QueryType::SOA.to_num() && rrset_type != NSEC && rrset_type != NSEC3 {
+                continue;
+            }
+            match self.verify_parent_rrset(name, rrset_type, &rrset, &sigs) {
+                Security::Secure => denial_records.extend(rrset),
+                Security::Insecure => return DsState::Insecure,
+                _ => return DsState::Bogus,
+            }
+        }
+        if denial_records.is_empty() {
+            println!("Unsigned answer to the DS lookup of {}", name);
+            return DsState::Bogus;
+        }
+
+        let denial = Denial::from_records(&denial_records);
+        if response.header.rcode == RCode::NXDOMAIN {
+            return match denial.proves_nxdomain(name) {
+                Proof::Proven => DsState::NoCut,
+                Proof::OptOut | Proof::Unsupported => DsState::Insecure,
+                Proof::Missing => DsState::Bogus,
+            };
+        }
+        match denial.proves_nodata(name, DS) {
+            Proof::Proven => {
+                // A delegation has NS records but no SOA at the parent side
+                let types = denial.nsec_matching(name).map(|nsec| nsec.types.clone()).or_else(|| {
+                    let nsec3s = denial.usable_nsec3s().ok()?;
+                    Denial::nsec3_matching(&nsec3s, name).map(|nsec3| nsec3.types.clone())
+                });
+                match types {
+                    Some(types) if types.contains(&QueryType::NS.to_num()) && !types.contains(&QueryType::SOA.to_num()) => DsState::Insecure,
+                    _ => DsState::NoCut,
+                }
+            }
+            Proof::OptOut | Proof::Unsupported => DsState::Insecure,
+            Proof::Missing => DsState::Bogus,
+        }
+    }
+
+    // RRsets about a delegation must be signed by a zone above it
+    fn verify_parent_rrset(&mut self, name: &Name, rrset_type: u16, rrset: &[DNSRecord], rrsigs: &[(Name, Rrsig)]) -> Security {
+        let owner = record_owner(&rrset[0]);
+        let parent_sigs: Vec<(Name, Rrsig)> = rrsigs.iter()
+            .filter(|(_, rrsig)| rrsig.signer != *name)
+            .cloned()
+            .collect();
+        self.verify_rrset(&owner, rrset_type, rrset, &parent_sigs)
+    }
+
+    // DNSKEYs of a zone, trusted through the DS records of the parent or the trust anchors
+    fn zone_keys(&mut self, zone: &Name) -> ZoneKeys {
+        if let Some(keys) = self.keys.get(zone) {
+            return keys.clone();
+        }
+        // Guards against loops while the chain is built
+        self.keys.insert(zone.clone(), ZoneKeys::Bogus);
+
+        let keys = if zone.is_root() {
+            let anchors = self.anchors.clone();
+            self.trusted_keys(zone, &anchors)
+        } else {
+            match self.ds_state(zone) {
+                DsState::Secure(ds) => self.trusted_keys(zone, &ds),
+                DsState::Insecure => ZoneKeys::Insecure,
+                DsState::NoCut | DsState::Bogus => ZoneKeys::Bogus,
+            }
+        };
+        self.keys.insert(zone.clone(), keys.clone());
+        keys
+    }
+
+    // The DNSKEY RRset must be signed by a key matching one of the DS records
+    fn trusted_keys(&mut self, zone: &Name, ds: &[Ds]) -> ZoneKeys {
+        let response = (self.lookup)(zone, QueryType::DNSKEY);
+        let records: Vec<DNSRecord> = response.answers.iter()
+            .filter(|record| record_type(record) == DNSKEY && record_owner(record) == *zone)
+            .cloned()
+            .collect();
+        let keys: Vec<Dnskey> = records.iter()
+            .filter_map(Dnskey::from_record)
+            .collect();
+
+        let entry_keys: Vec<Dnskey> = keys.iter()
+            .filter(|key| ds.iter().any(|ds| ds.matches(zone, key)))
+            .cloned()
+            .collect();
+        if entry_keys.is_empty() {
+            println!("No DNSKEY of {} matches its DS records", display_name(zone));
+            return ZoneKeys::Bogus;
+        }
+
+        let signed = get_rrsigs(&response.answers).iter()
+            .filter(|(owner, rrsig)| owner == zone && rrsig.type_covered == DNSKEY && rrsig.signer == *zone)
+            .any(|(_, rrsig)| self.verify_rrsig(zone, &records, rrsig, &entry_keys));
+        if !signed {
+            println!("DNSKEY RRset of {} is not signed by a trusted key", display_name(zone));
+            return ZoneKeys::Bogus;
+        }
+
+        ZoneKeys::Secure(keys)
+    }
+}
+
+fn display_name(name: &Name) -> String {
+    if name.is_root() { ".".to_string() } else { name.to_string() }
+}
+
+// The least secure status wins
+fn combine(a: Security, b: Security) -> Security {
+    let rank = |security: Security| match security {
+        Security::Secure => 0,
+        Security::Insecure => 1,
+        Security::Indeterminate => 2,
+        Security::Bogus => 3,
+    };
+    if rank(b) > rank(a) { b } else { a }
+}
+
+// Remove the DNSSEC records a client did not ask for (RFC 4035 section 3.2.1)
+pub fn strip_dnssec_records(packet: &mut DNSPacket, qtype: QueryType) {
+    let qtype = qtype.to_num();
+    let keep = |record: &DNSRecord| {
+        let record_type = record_type(record);
+        record_type == qtype || !matches!(record_type, RRSIG | NSEC | NSEC3)
+    };
+    packet.answers.retain(keep);
+    packet.authorities.retain(keep);
+    packet.resources.retain(keep);
+}
+
+// The validated NSEC and NSEC3 records proving that name has no records of type qtype,
+// with the RCODE of the answer; used to answer from the cache (RFC 8198 section 5)
+// Opt-out spans prove nothing: an unsigned delegation could be in them
+pub fn find_denial(records: &[DNSRecord], name: &Name, qtype: u16) -> Option<(RCode, Vec<DNSRecord>)> {
+    // A proof can only use the records matching or covering the name,
+    // its ancestors or the wildcards below them
+    let mut candidates = vec![name.clone()];
+    for n in 0..name.label_count() {
+        let ancestor = name.suffix(n);
+        candidates.extend(wildcard_of(&ancestor));
+        candidates.push(ancestor);
+    }
+
+    let mut hashes: HashMap<(Vec<u8>, u16, Name), String> = HashMap::new();
+    let relevant: Vec<DNSRecord> = records.iter()
+        .filter(|record| match record {
+            DNSRecord::NSEC { next, .. } => {
+                let owner = record_owner(record);
+                candidates.iter().any(|candidate| *candidate == owner || nsec_covers(&owner, next, candidate))
+            }
+            DNSRecord::NSEC3 { iterations, salt, next_hashed, .. } => {
+                let owner = record_owner(record);
+                let owner_hash = owner.labels().first().map(|label| escape_label(&label.to_ascii_lowercase())).unwrap_or_default();
+                let next_hash = base32hex(next_hashed);
+                candidates.iter().any(|candidate| {
+                    let hash = hashes.entry((salt.clone(), *iterations, candidate.clone()))
+                        .or_insert_with(|| base32hex(&nsec3_hash(candidate, salt, *iterations)));
+                    *hash == owner_hash || nsec3_covers(&owner_hash, &next_hash, hash)
+                })
+            }
+            _ => false,
+        })
+        .cloned()
+        .collect();
+
+    let denial = Denial::from_records(&relevant);
+    if denial.proves_nxdomain(name) == Proof::Proven {
+        return Some((RCode::NXDOMAIN, relevant));
+    }
+    if denial.proves_nodata(name, qtype) == Proof::Proven {
+        return Some((RCode::NOERROR, relevant));
+    }
+    None
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+
+use crate::name::Name;
+use crate::packet::{DNSPacket, QueryType, RCode};
+use crate::server_config::{FailoverStrategy, ForwardConfig};
+use crate::stub_resolver::udp_lookup;
+use crate::tsig::TsigKey;
+
+// SRTT of a failing upstream is kept below this many timeouts,
+// so that a few good answers bring it back into use
+const MAX_FAILURE_PENALTY: f64 = 4.0;
+
+// Smoothed round trip times of the upstreams, in milliseconds
+// Updated after every forwarded query, used by FailoverStrategy::Fastest
+#[derive(Default, Debug)]
+pub struct UpstreamStats {
+    pub srtt: RwLock<HashMap<(Ipv4Addr, u16), f64>>,
+}
+
+impl Clone for UpstreamStats {
+    fn clone(&self) -> Self {
+        let srtt_clone = self.srtt.read().unwrap().clone();
+        UpstreamStats {
+            srtt: RwLock::new(srtt_clone),
+        }
+    }
+}
+
+impl UpstreamStats {
+    pub fn new() -> UpstreamStats {
+        UpstreamStats {
+            srtt: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Upstreams never queried get 0, so each one is tried at least once
+    pub fn get_srtt(&self, server: (Ipv4Addr, u16)) -> f64 {
+        match self.srtt.read() {
+            Ok(srtt) => srtt.get(&server).copied().unwrap_or(0.0),
+            Err(_) => 0.0,
+        }
+    }
+
+    // Exponentially weighted moving average of the RTT
+    pub fn record_rtt(&self, server: (Ipv4Addr, u16), rtt: Duration) {
+        if let Ok(mut srtt) = self.srtt.write() {
+            let sample = rtt.as_secs_f64() * 1000.0;
+            let entry = srtt.entry(server).or_insert(sample);
+            *entry = 0.7 * *entry + 0.3 * sample;
+        }
+    }
+
+    // A failed upstream is pushed back: its SRTT becomes at least the timeout
+    // and doubles with each failure, up to MAX_FAILURE_PENALTY timeouts
+    pub fn record_failure(&self, server: (Ipv4Addr, u16), timeout: Duration) {
+        if let Ok(mut srtt) = self.srtt.write() {
+            let penalty = timeout.as_secs_f64() * 1000.0;
+            srtt.entry(server)
+                .and_modify(|entry| *entry = (*entry * 2.0).clamp(penalty, penalty * MAX_FAILURE_PENALTY))
+                .or_insert(penalty);
+        }
+    }
+}
+
+// Forward the query to the configured upstreams
+// Upstreams are tried in failover order, for 1 + retries rounds
+// A SERVFAIL packet is returned when no upstream gives a usable answer
+// Queries are signed when a TSIG key is given
+// With dnssec_ok the upstream is asked for the RRSIG, NSEC and NSEC3 records too
+pub fn forward_lookup(qname: &Name, qtype: QueryType, config: &ForwardConfig, stats: &UpstreamStats, rd_flag: bool, key: Option<&TsigKey>, dnssec_ok: bool) -> DNSPacket {
+    let timeout = Duration::from_millis(config.timeout_ms);
+
+    // Upstreams with an invalid address are skipped
+    let mut servers = Vec::new();
+    for upstream in &config.upstreams {
+        match upstream.host.parse::<Ipv4Addr>() {
+            Ok(host) => servers.push((host, upstream.port)),
+            Err(e) => println!("Skipping upstream {:?}: {:?}", upstream.host, e),
+        }
+    }
+
+    match config.failover {
+        FailoverStrategy::Sequential => {}
+        FailoverStrategy::Random => servers.shuffle(&mut rand::thread_rng()),
+        FailoverStrategy::Fastest => {
+            servers.sort_by(|a, b| stats.get_srtt(*a).total_cmp(&stats.get_srtt(*b)));
+        }
+    }
+
+    for round in 0..=config.retries {
+        for server in &servers {
+            println!("Forwarding to {:?} (attempt {})", server, round + 1);
+
+            let start = Instant::now();
+            match udp_lookup(qname, qtype, *server, rd_flag, timeout, key, dnssec_ok) {
+                // SERVFAIL and REFUSED mean this upstream cannot help; try the next one
+                Ok(response) if response.header.rcode == RCode::SERVFAIL || response.header.rcode == RCode::REFUSED => {
+                    println!("Upstream {:?} answered {:?}", server, response.header.rcode);
+                    stats.record_rtt(*server, start.elapsed());
+                }
+                Ok(response) => {
+                    stats.record_rtt(*server, start.elapsed());
+                    return response;
+                }
+                Err(e) => {
+                    println!("Forwarding to {:?} failed: {:?}", server, e);
+                    stats.record_failure(*server, timeout);
+                }
+            }
+        }
+    }
+
+    println!("All upstreams failed for {}", qname);
+    let mut response = DNSPacket::new();
+    response.header.rcode = RCode::SERVFAIL;
+    response
+}
@@ -0,0 +1,23 @@
+pub mod name;
+pub mod parser;
+pub mod packet;
+pub mod writer;
+pub mod stub_resolver;
+pub mod recursive_resolver;
+pub mod forward_resolver;
+pub mod server;
+pub mod tcp_connection;
+pub mod udp_connection;
+pub mod server_config;
+pub mod resolve_strategy;
+pub mod cache;
+pub mod start_servers;
+pub mod zone;
+pub mod zone_file;
+pub mod zone_transfer;
+pub mod acl;
+pub mod secondary;
+pub mod update;
+pub mod tsig;
+pub mod dnssec;
+pub mod signer;
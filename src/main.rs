@@ -1,18 +1,7 @@
-pub mod parser;
-pub mod packet;
-pub mod writer;
-pub mod stub_resolver;
-pub mod recursive_resolver;
-pub mod server;
-pub mod tcp_connection;
-pub mod udp_connection;
-pub mod server_config;
-pub mod resolve_strategy;
-pub mod cache;
-pub mod start_servers;
-use cache::Cache;
-use packet::{DNSRecord, QueryType, RCode};
-use start_servers::init_servers;
+use rusty_twisted::cache::Cache;
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::{DNSRecord, QueryType, RCode};
+use rusty_twisted::start_servers::init_servers;
 
 
 fn main() {
@@ -42,48 +31,48 @@ fn test_cache() {
     let mut cache = Cache::new();
 
     // Verify that no data is returned when nothing is present
-    if cache.lookup("www.google.com", QueryType::A).is_some() {
+    if cache.lookup(&Name::try_from("www.google.com").unwrap(), QueryType::A).is_some() {
         panic!()
     }
 
     // Register a negative cache entry
-    cache.store_nxdomain("www.google.com", QueryType::A, 3600);
+    cache.store_nxdomain(&Name::try_from("www.google.com").unwrap(), QueryType::A, 3600);
 
     // Verify that we get a response, with the NXDOMAIN flag set
-    if let Some(packet) = cache.lookup("www.google.com", QueryType::A) {
+    if let Some(packet) = cache.lookup(&Name::try_from("www.google.com").unwrap(), QueryType::A) {
         assert_eq!(RCode::NXDOMAIN, packet.header.rcode);
     }
 
     // Register a negative cache entry with no TTL
-    cache.store_nxdomain("www.yahoo.com", QueryType::A, 0);
+    cache.store_nxdomain(&Name::try_from("www.yahoo.com").unwrap(), QueryType::A, 0);
 
     // And check that no such result is actually returned, since it's expired
-    if cache.lookup("www.yahoo.com", QueryType::A).is_some() {
+    if cache.lookup(&Name::try_from("www.yahoo.com").unwrap(), QueryType::A).is_some() {
         panic!()
     }
 
     // Now add some actual records
     let mut records = Vec::new();
     records.push(DNSRecord::A {
-        domain: "www.google.com".to_string(),
+        domain: Name::try_from("www.google.com").unwrap(),
         addr: "127.0.0.1".parse().unwrap(),
         ttl: 3600000,
     });
     records.push(DNSRecord::A {
-        domain: "www.yahoo.com".to_string(),
+        domain: Name::try_from("www.yahoo.com").unwrap(),
         addr: "127.0.0.2".parse().unwrap(),
         ttl: 0,
     });
     records.push(DNSRecord::CNAME {
-        domain: "www.microsoft.com".to_string(),
-        host: "www.somecdn.com".to_string(),
+        domain: Name::try_from("www.microsoft.com").unwrap(),
+        host: Name::try_from("www.somecdn.com").unwrap(),
         ttl: 3600000,
     });
 
-    cache.store(&records);
+    cache.store(&records, false);
 
     // Test for successful lookup
-    if let Some(packet) = cache.lookup("www.google.com", QueryType::A) {
+    if let Some(packet) = cache.lookup(&Name::try_from("www.google.com").unwrap(), QueryType::A) {
         // assert_eq!(records[0], packet.answers[0]);
         println!("Cache answer: {:?}", packet.answers[0]);
     } else {
@@ -91,33 +80,33 @@ fn test_cache() {
     }
 
     // Test for failed lookup, since no CNAME's are known for this domain
-    if cache.lookup("www.google.com", QueryType::CNAME).is_some() {
+    if cache.lookup(&Name::try_from("www.google.com").unwrap(), QueryType::CNAME).is_some() {
         panic!();
     }
 
     // Check for successful CNAME lookup
-    if let Some(packet) = cache.lookup("www.microsoft.com", QueryType::CNAME) {
+    if let Some(packet) = cache.lookup(&Name::try_from("www.microsoft.com").unwrap(), QueryType::CNAME) {
         assert_eq!(records[2], packet.answers[0]);
     } else {
         panic!();
     }
 
     // This lookup should fail, since it has expired due to the 0 second TTL
-    if cache.lookup("www.yahoo.com", QueryType::A).is_some() {
+    if cache.lookup(&Name::try_from("www.yahoo.com").unwrap(), QueryType::A).is_some() {
         panic!();
     }
 
     let mut records2 = Vec::new();
     records2.push(DNSRecord::A {
-        domain: "www.yahoo.com".to_string(),
+        domain: Name::try_from("www.yahoo.com").unwrap(),
         addr: "127.0.0.2".parse().unwrap(),
         ttl: 36000000,
     });
 
-    cache.store(&records2);
+    cache.store(&records2, false);
 
     // And now it should succeed, since the record has been store
-    if !cache.lookup("www.yahoo.com", QueryType::A).is_some() {
+    if !cache.lookup(&Name::try_from("www.yahoo.com").unwrap(), QueryType::A).is_some() {
         panic!();
     }
 
@@ -127,7 +116,7 @@ fn test_cache() {
         1,
         cache
             .domain_entries
-            .get(&"www.google.com".to_string())
+            .get(&Name::try_from("www.google.com").unwrap())
             .unwrap()
             .hits
     );
@@ -135,7 +124,7 @@ fn test_cache() {
         2,
         cache
             .domain_entries
-            .get(&"www.google.com".to_string())
+            .get(&Name::try_from("www.google.com").unwrap())
             .unwrap()
             .updates
     );
@@ -143,7 +132,7 @@ fn test_cache() {
         1,
         cache
             .domain_entries
-            .get(&"www.yahoo.com".to_string())
+            .get(&Name::try_from("www.yahoo.com").unwrap())
             .unwrap()
             .hits
     );
@@ -151,7 +140,7 @@ fn test_cache() {
         3,
         cache
             .domain_entries
-            .get(&"www.yahoo.com".to_string())
+            .get(&Name::try_from("www.yahoo.com").unwrap())
             .unwrap()
             .updates
     );
@@ -159,7 +148,7 @@ fn test_cache() {
         1,
         cache
             .domain_entries
-            .get(&"www.microsoft.com".to_string())
+            .get(&Name::try_from("www.microsoft.com").unwrap())
             .unwrap()
             .updates
     );
@@ -167,7 +156,7 @@ fn test_cache() {
         1,
         cache
             .domain_entries
-            .get(&"www.microsoft.com".to_string())
+            .get(&Name::try_from("www.microsoft.com").unwrap())
             .unwrap()
             .hits
     );
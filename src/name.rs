@@ -0,0 +1,286 @@
+use std::{cmp::Ordering, fmt, hash::{Hash, Hasher}, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// Limits of RFC 1035 section 2.3.4; the name length counts the length bytes
+pub const MAX_LABEL_LENGTH: usize = 63;
+pub const MAX_NAME_LENGTH: usize = 255;
+
+// Error while building a name from text or labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameError {
+    // Label longer than 63 bytes
+    LabelTooLong(usize),
+    // Name longer than 255 bytes on the wire
+    NameTooLong(usize),
+    // Two dots in a row, or a dot at the start of a name
+    EmptyLabel,
+    // Backslash at the end of the text, or \DDD above 255
+    BadEscape,
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NameError::LabelTooLong(length) => write!(f, "label of {} bytes, longer than {}", length, MAX_LABEL_LENGTH),
+            NameError::NameTooLong(length) => write!(f, "name of {} bytes, longer than {}", length, MAX_NAME_LENGTH),
+            NameError::EmptyLabel => write!(f, "empty label"),
+            NameError::BadEscape => write!(f, "bad escape sequence"),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+// A domain name, stored label by label from the leftmost one; the root has no labels
+// Equality, hashing and ordering ignore ASCII case (RFC 4343), the labels keep their case
+// The order is the canonical order of RFC 4034 section 6.1
+#[derive(Clone, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Name {
+        Name { labels: Vec::new() }
+    }
+
+    // Name from its labels, checking the lengths
+    pub fn from_labels(labels: Vec<Vec<u8>>) -> Result<Name, NameError> {
+        if labels.iter().any(|label| label.is_empty()) {
+            return Err(NameError::EmptyLabel);
+        }
+        if let Some(label) = labels.iter().find(|label| label.len() > MAX_LABEL_LENGTH) {
+            return Err(NameError::LabelTooLong(label.len()));
+        }
+
+        let name = Name { labels };
+        if name.wire_length() > MAX_NAME_LENGTH {
+            return Err(NameError::NameTooLong(name.wire_length()));
+        }
+        Ok(name)
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // Length on the wire, uncompressed: a length byte per label and the root
+    pub fn wire_length(&self) -> usize {
+        self.labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1
+    }
+
+    // True if the name is equal to or below zone, comparing whole labels
+    pub fn is_subdomain_of(&self, zone: &Name) -> bool {
+        self.labels.len() >= zone.labels.len()
+            && self.labels.iter().rev().zip(zone.labels.iter().rev()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    // The name without its leftmost label; the root has no parent
+    pub fn parent(&self) -> Option<Name> {
+        if self.is_root() {
+            return None;
+        }
+        Some(Name { labels: self.labels[1..].to_vec() })
+    }
+
+    // The last n labels of the name, the whole name if it has fewer
+    pub fn suffix(&self, n: usize) -> Name {
+        Name { labels: self.labels[self.labels.len() - n.min(self.labels.len())..].to_vec() }
+    }
+
+    // The name one label below this one, like the wildcard *.name
+    pub fn child(&self, label: &[u8]) -> Result<Name, NameError> {
+        let mut labels = vec![label.to_vec()];
+        labels.extend_from_slice(&self.labels);
+        Name::from_labels(labels)
+    }
+
+    // The same name in lowercase, as used in canonical forms (RFC 4034 section 6.2)
+    pub fn to_lowercase(&self) -> Name {
+        Name { labels: self.labels.iter().map(|label| label.to_ascii_lowercase()).collect() }
+    }
+}
+
+// Text form of a label (RFC 4343 section 2.1): dots, backslashes and the
+// characters special in master files get a backslash, other bytes outside
+// printable ASCII are written as \DDD
+pub fn escape_label(label: &[u8]) -> String {
+    let mut text = String::with_capacity(label.len());
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                text.push('\\');
+                text.push(byte as char);
+            }
+            0x21..=0x7E => text.push(byte as char),
+            _ => text.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    text
+}
+
+// Split a name in text form into its labels, undoing the escapes
+// Empty labels are kept: "a." gives ["a", ""]
+fn split_labels(text: &str) -> Result<Vec<Vec<u8>>, NameError> {
+    let bytes = text.as_bytes();
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'.' => {
+                labels.push(std::mem::take(&mut label));
+                i += 1;
+            }
+            b'\\' => {
+                let digits = &bytes[i + 1..bytes.len().min(i + 4)];
+                if digits.len() == 3 && digits.iter().all(u8::is_ascii_digit) {
+                    let value = digits.iter().fold(0u32, |value, digit| value * 10 + (digit - b'0') as u32);
+                    if value > 255 {
+                        return Err(NameError::BadEscape);
+                    }
+                    label.push(value as u8);
+                    i += 4;
+                } else {
+                    // Any other character stands for itself
+                    label.push(*bytes.get(i + 1).ok_or(NameError::BadEscape)?);
+                    i += 2;
+                }
+            }
+            byte => {
+                label.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    labels.push(label);
+    Ok(labels)
+}
+
+// Text form: escaped labels joined by dots, without the trailing dot; the root is ""
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, label) in self.labels.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            f.write_str(&escape_label(label))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}
+
+// Parse a name in text form, with its escapes (RFC 4343 section 2.1)
+// "" and "." are the root, a trailing dot is allowed
+impl FromStr for Name {
+    type Err = NameError;
+
+    fn from_str(text: &str) -> Result<Name, NameError> {
+        if text.is_empty() || text == "." {
+            return Ok(Name::root());
+        }
+        let mut labels = split_labels(text)?;
+        if labels.last().is_some_and(|label| label.is_empty()) {
+            labels.pop();
+        }
+        Name::from_labels(labels)
+    }
+}
+
+// Names written in the code, with the checks of FromStr
+impl TryFrom<&str> for Name {
+    type Error = NameError;
+
+    fn try_from(text: &str) -> Result<Name, NameError> {
+        text.parse()
+    }
+}
+
+impl TryFrom<String> for Name {
+    type Error = NameError;
+
+    fn try_from(text: String) -> Result<Name, NameError> {
+        text.parse()
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.labels.len() == other.labels.len()
+            && self.labels.iter().zip(other.labels.iter()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for Name {}
+
+// Compared with the name parsed from the text; text that is not a name is never equal
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        other.parse::<Name>().is_ok_and(|other| *self == other)
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl PartialEq<String> for Name {
+    fn eq(&self, other: &String) -> bool {
+        *self == *other.as_str()
+    }
+}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.labels.len().hash(state);
+        for label in &self.labels {
+            label.to_ascii_lowercase().hash(state);
+        }
+    }
+}
+
+impl Ord for Name {
+    fn cmp(&self, other: &Name) -> Ordering {
+        self.labels.iter().rev().map(|label| label.to_ascii_lowercase())
+            .cmp(other.labels.iter().rev().map(|label| label.to_ascii_lowercase()))
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Name) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Stored as text in the configuration, the journal and the zone copies
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Name, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(de::Error::custom)
+    }
+}
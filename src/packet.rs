@@ -1,48 +1,88 @@
 use serde_derive::{Deserialize, Serialize};
 
-use crate::parser::PacketParser;
+use crate::name::Name;
+use crate::parser::{PacketParser, ParseError, ParseResult};
 use crate::writer::PacketWriter;
 
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 // ________________________________________________ HEADER _______________________________________________________________
 // RCODE - Response Code FLAG
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMP,
+    REFUSED,
+    YXDOMAIN, // RFC 2136
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
+    BADVERS, // EDNS extended RCODE
+    UNKNOWN(u16), // Kept as received, so that it is answered or forwarded unchanged
 }
 
 impl RCode {
-    pub fn get_rcode(num: u8) -> RCode {
+    // RCODEs above 15 need the extended bits of the OPT record
+    pub fn get_rcode(num: u16) -> RCode {
         match num {
+            0 => RCode::NOERROR,
             1 => RCode::FORMERR,
             2 => RCode::SERVFAIL,
             3 => RCode::NXDOMAIN,
             4 => RCode::NOTIMP, 
             5 => RCode::REFUSED,
-            0 | _ => RCode::NOERROR,
+            6 => RCode::YXDOMAIN,
+            7 => RCode::YXRRSET,
+            8 => RCode::NXRRSET,
+            9 => RCode::NOTAUTH,
+            10 => RCode::NOTZONE,
+            16 => RCode::BADVERS,
+            _ => RCode::UNKNOWN(num),
+        }
+    }
+
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            RCode::NOERROR => 0,
+            RCode::FORMERR => 1,
+            RCode::SERVFAIL => 2,
+            RCode::NXDOMAIN => 3,
+            RCode::NOTIMP => 4,
+            RCode::REFUSED => 5,
+            RCode::YXDOMAIN => 6,
+            RCode::YXRRSET => 7,
+            RCode::NXRRSET => 8,
+            RCode::NOTAUTH => 9,
+            RCode::NOTZONE => 10,
+            RCode::BADVERS => 16,
+            RCode::UNKNOWN(num) => num,
         }
     }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OpCode {
-    QUERY = 0,
-    IQUERY = 1,
-    STATUS = 2,
+    QUERY,
+    IQUERY,
+    STATUS,
+    NOTIFY, // RFC 1996
+    UPDATE, // RFC 2136
+    UNKNOWN(u8), // Kept as received, so that it is answered with NOTIMP
 }
 
 impl OpCode {
     pub fn get_opcode(num: u8) -> OpCode {
         match num {
+            0 => OpCode::QUERY,
             1 => OpCode::IQUERY,
             2 => OpCode::STATUS,
-            0 | _ => OpCode::QUERY,
+            4 => OpCode::NOTIFY,
+            5 => OpCode::UPDATE,
+            _ => OpCode::UNKNOWN(num),
         }
     }
 
@@ -50,13 +90,16 @@ impl OpCode {
         match *self {
             OpCode::IQUERY => 1,
             OpCode::STATUS => 2,
+            OpCode::NOTIFY => 4,
+            OpCode::UPDATE => 5,
             OpCode::QUERY => 0,
+            OpCode::UNKNOWN(num) => num,
         }
     }
 }
 
 // DNS HEADER FIELDS
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DNSHeader {
     // Identification
     pub id: u16, 
@@ -68,7 +111,7 @@ pub struct DNSHeader {
     pub truncation: bool,
     pub recursion_desired: bool,
     pub recursion_available: bool,
-    pub zero: bool, // 3 bits in future use
+    pub zero: bool, // 1 bit, reserved (Z)
     pub checking_disabled: bool,   // 1 bit
     pub authed_data: bool,         // 1 bit
     pub rcode: RCode, 
@@ -103,10 +146,10 @@ impl DNSHeader {
         }
     }
 
-    pub fn parse_header(&mut self, parser: &mut PacketParser) {
-        self.id = parser.parse_u16(); // 16 bits
+    pub fn parse_header(&mut self, parser: &mut PacketParser) -> ParseResult<()> {
+        self.id = parser.parse_u16()?; // 16 bits
 
-        let flags = parser.parse_u16();
+        let flags = parser.parse_u16()?;
         let a = (flags >> 8) as u8; // first 8 bits
         let b = (flags & 0xFF) as u8; // last 8 bits
 
@@ -116,16 +159,17 @@ impl DNSHeader {
         self.opcode = OpCode::get_opcode((a >> 3) & 0x0F); // 4 bits
         self.query = (a & (1 << 7)) > 0; // 1 bit
 
-        self.rcode = RCode::get_rcode(b & 0x0F); // 4 bits
+        self.rcode = RCode::get_rcode((b & 0x0F) as u16); // 4 bits, the OPT record may extend them
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
-        self.zero = (b & (a << 6)) > 0; // 3 bits
+        self.zero = (b & (1 << 6)) > 0; // 1 bit
         self.recursion_available = (b & (1 << 7)) > 0; // 1 bit
 
-        self.qd_count = parser.parse_u16();
-        self.an_count = parser.parse_u16();
-        self.ns_count = parser.parse_u16();
-        self.ar_count = parser.parse_u16();
+        self.qd_count = parser.parse_u16()?;
+        self.an_count = parser.parse_u16()?;
+        self.ns_count = parser.parse_u16()?;
+        self.ar_count = parser.parse_u16()?;
+        Ok(())
     }
 
     pub fn write_header(&self, writer: &mut PacketWriter){
@@ -140,7 +184,7 @@ impl DNSHeader {
         );
 
         writer.write_u8(
-            (self.rcode as u8)
+            ((self.rcode.to_num() & 0x0F) as u8)
                 | ((self.checking_disabled as u8) << 4)
                 | ((self.authed_data as u8) << 5)
                 | ((self.zero as u8) << 6)
@@ -164,8 +208,22 @@ pub enum QueryType {
     A,      // 1
     NS,     // 2
     CNAME,  // 5
+    SOA,    // 6
+    PTR,    // 12
     MX,     // 15
+    TXT,    // 16
     AAAA,   // 28
+    SRV,    // 33
+    OPT,    // 41
+    DS,     // 43
+    RRSIG,  // 46
+    NSEC,   // 47
+    DNSKEY, // 48
+    NSEC3,  // 50
+    NSEC3PARAM, // 51
+    TSIG,   // 250
+    IXFR,   // 251
+    AXFR,   // 252
 }
 
 impl QueryType {
@@ -175,8 +233,22 @@ impl QueryType {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
             QueryType::MX => 15,
-            QueryType::AAAA => 28
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::NSEC3PARAM => 51,
+            QueryType::TSIG => 250,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
         }
     }
 
@@ -185,8 +257,22 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            51 => QueryType::NSEC3PARAM,
+            250 => QueryType::TSIG,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
             _ => QueryType::UNKNOWN(num),
         }
     }
@@ -195,7 +281,7 @@ impl QueryType {
 // DNS Question
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSQuestion {
-    pub qname: String,
+    pub qname: Name,
     pub qtype: QueryType,
     pub class: u16,
 }
@@ -203,67 +289,190 @@ pub struct DNSQuestion {
 impl DNSQuestion {
     pub fn new() -> DNSQuestion {
         DNSQuestion {
-            qname: "".to_owned(),
+            qname: Name::root(),
             qtype: QueryType::UNKNOWN(0),
-            class: 0,
+            class: 1,
         }
     }
 
-    pub fn parse_question(&mut self, parser: &mut PacketParser) {
-        self.qname = parser.parse_qname();
-        self.qtype = QueryType::get_query_type(parser.parse_u16());
-        self.class = parser.parse_u16();
+    pub fn parse_question(&mut self, parser: &mut PacketParser) -> ParseResult<()> {
+        self.qname = parser.parse_qname()?;
+        self.qtype = QueryType::get_query_type(parser.parse_u16()?);
+        self.class = parser.parse_u16()?;
+        Ok(())
     }
 
     pub fn write_question(&self, buffer: &mut PacketWriter){
-        buffer.write_qname(&self.qname);
+        buffer.write_name(&self.qname);
 
         let qtype_num = self.qtype.to_num();
         buffer.write_u16(qtype_num);
-        buffer.write_u16(1);
+        buffer.write_u16(self.class);
     }
 }
 // ________________________________________________ ANSWER _______________________________________________________________
+// EDNS(0) option carried in the RDATA of an OPT record
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+// UDP payload size advertised in our own OPT records
+pub const EDNS_UDP_SIZE: u16 = 4096;
+
 // DNS Record
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 
 pub enum DNSRecord {
     UNKNOWN {
-        domain: String,
+        domain: Name,
         qtype: u16, 
-        data_len: u16, 
+        class: u16,
+        data: Vec<u8>, // raw RDATA, kept byte for byte (RFC 3597)
         ttl: u32,
     }, // 0
     A {
-        domain: String, 
+        domain: Name, 
         addr: Ipv4Addr, 
         ttl: u32,
     }, // 1
     NS {
-        domain: String,
-        host: String, 
+        domain: Name,
+        host: Name, 
         ttl: u32,
     }, // 2
     CNAME {
-        domain: String, 
-        host: String, 
+        domain: Name, 
+        host: Name, 
         ttl: u32, 
     }, // 5
+    SOA {
+        domain: Name,
+        mname: Name,
+        rname: Name,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    }, // 6
+    PTR {
+        domain: Name,
+        host: Name,
+        ttl: u32,
+    }, // 12
     MX {
-        domain: String, 
+        domain: Name, 
         priority: u16, 
-        host: String, 
+        host: Name, 
         ttl: u32,
     }, // 15
+    TXT {
+        domain: Name,
+        data: Vec<Vec<u8>>, // character-strings, binary safe
+        ttl: u32,
+    }, // 16
     AAAA {
-        domain: String,
+        domain: Name,
         addr: Ipv6Addr, 
         ttl: u32,
     }, // 28
-
+    SRV {
+        domain: Name,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: Name,
+        ttl: u32,
+    }, // 33
+    OPT {
+        packet_len: u16, // requestor's UDP payload size, sent in the CLASS field
+        ext_rcode: u8,   // upper 8 bits of the 12 bit RCODE
+        version: u8,
+        dnssec_ok: bool, // DO bit
+        flags: u16,      // remaining 15 bits, must be zero
+        options: Vec<EdnsOption>,
+    }, // 41
+    DS {
+        domain: Name,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+    }, // 43
+    RRSIG {
+        domain: Name,
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32, // seconds since the epoch, in serial number arithmetic
+        inception: u32,
+        key_tag: u16,
+        signer: Name,
+        signature: Vec<u8>,
+        ttl: u32,
+    }, // 46
+    NSEC {
+        domain: Name,
+        next: Name,      // next owner name, kept in its original case (RFC 6840 section 5.1)
+        types: Vec<u16>, // types present at the owner, from the type bitmap
+        ttl: u32,
+    }, // 47
+    DNSKEY {
+        domain: Name,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    }, // 48
+    NSEC3 {
+        domain: Name,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>, // raw hash, not base32hex
+        types: Vec<u16>,
+        ttl: u32,
+    }, // 50
+    NSEC3PARAM {
+        domain: Name,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        ttl: u32,
+    }, // 51
+    TSIG {
+        domain: Name,      // key name
+        algorithm: Name,   // like hmac-sha256
+        time_signed: u64,  // 48 bits, seconds since the epoch
+        fudge: u16,        // allowed clock skew in seconds
+        mac: Vec<u8>,
+        original_id: u16,
+        error: u16,
+        other: Vec<u8>,
+    }, // 250
 }
 
 impl DNSRecord {
+    // Our own OPT record, advertising EDNS_UDP_SIZE
+    pub fn new_opt(dnssec_ok: bool) -> DNSRecord {
+        DNSRecord::OPT {
+            packet_len: EDNS_UDP_SIZE,
+            ext_rcode: 0,
+            version: 0,
+            dnssec_ok,
+            flags: 0,
+            options: Vec::new(),
+        }
+    }
+
     pub fn get_query_type(self) -> QueryType{
         match self {
             DNSRecord::A { domain: _, addr: _, ttl: _ } => QueryType::A,
@@ -271,21 +480,100 @@ impl DNSRecord {
             DNSRecord::CNAME { domain: _, host: _, ttl: _ } => QueryType::CNAME,
             DNSRecord::MX { domain: _, priority: _, host: _, ttl: _ } => QueryType::MX,
             DNSRecord::NS { domain: _, host: _, ttl: _ } => QueryType::NS,
-            DNSRecord::UNKNOWN { domain: _, qtype: _, data_len: _, ttl: _ } => todo!(),
+            DNSRecord::TXT { domain: _, data: _, ttl: _ } => QueryType::TXT,
+            DNSRecord::SRV { .. } => QueryType::SRV,
+            DNSRecord::OPT { .. } => QueryType::OPT,
+            DNSRecord::DS { .. } => QueryType::DS,
+            DNSRecord::RRSIG { .. } => QueryType::RRSIG,
+            DNSRecord::NSEC { .. } => QueryType::NSEC,
+            DNSRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DNSRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DNSRecord::NSEC3PARAM { .. } => QueryType::NSEC3PARAM,
+            DNSRecord::TSIG { .. } => QueryType::TSIG,
+            DNSRecord::SOA { .. } => QueryType::SOA,
+            DNSRecord::PTR { domain: _, host: _, ttl: _ } => QueryType::PTR,
+            DNSRecord::UNKNOWN { domain: _, qtype, class: _, data: _, ttl: _ } => QueryType::UNKNOWN(qtype),
         }
     }
 
-    pub fn get_domain(self) -> Option<String> {
+    pub fn get_domain(self) -> Option<Name> {
         match self {
             DNSRecord::A { domain, addr: _, ttl : _} => Some(domain),
             DNSRecord::AAAA { domain, addr: _, ttl: _ } => Some(domain),
             DNSRecord::CNAME { domain, host: _, ttl: _ } => Some(domain),
             DNSRecord::MX { domain, priority: _, host: _, ttl: _ } => Some(domain),
             DNSRecord::NS { domain, host: _, ttl: _ } => Some(domain),
-            DNSRecord::UNKNOWN { domain: _, qtype: _, data_len: _, ttl: _ } => None,
+            DNSRecord::TXT { domain, data: _, ttl: _ } => Some(domain),
+            DNSRecord::SRV { domain, .. } => Some(domain),
+            DNSRecord::OPT { .. } => None,
+            DNSRecord::DS { domain, .. }
+            | DNSRecord::RRSIG { domain, .. }
+            | DNSRecord::NSEC { domain, .. }
+            | DNSRecord::DNSKEY { domain, .. }
+            | DNSRecord::NSEC3 { domain, .. }
+            | DNSRecord::NSEC3PARAM { domain, .. } => Some(domain),
+            DNSRecord::TSIG { domain, .. } => Some(domain),
+            DNSRecord::SOA { domain, .. } => Some(domain),
+            DNSRecord::PTR { domain, host: _, ttl: _ } => Some(domain),
+            DNSRecord::UNKNOWN { domain, qtype: _, class: _, data: _, ttl: _ } => Some(domain),
+        }
+    }
+
+    // Change the owner name of the record, used for wildcard synthesis
+    pub fn set_domain(&mut self, new_domain: &Name) {
+        match self {
+            DNSRecord::A { domain, .. }
+            | DNSRecord::AAAA { domain, .. }
+            | DNSRecord::CNAME { domain, .. }
+            | DNSRecord::MX { domain, .. }
+            | DNSRecord::NS { domain, .. }
+            | DNSRecord::TXT { domain, .. }
+            | DNSRecord::SRV { domain, .. }
+            | DNSRecord::SOA { domain, .. }
+            | DNSRecord::PTR { domain, .. }
+            | DNSRecord::DS { domain, .. }
+            | DNSRecord::RRSIG { domain, .. }
+            | DNSRecord::NSEC { domain, .. }
+            | DNSRecord::DNSKEY { domain, .. }
+            | DNSRecord::NSEC3 { domain, .. }
+            | DNSRecord::NSEC3PARAM { domain, .. }
+            | DNSRecord::TSIG { domain, .. }
+            | DNSRecord::UNKNOWN { domain, .. } => *domain = new_domain.clone(),
+            DNSRecord::OPT { .. } => {}
         }
     }
 
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DNSRecord::A { ttl, .. }
+            | DNSRecord::AAAA { ttl, .. }
+            | DNSRecord::CNAME { ttl, .. }
+            | DNSRecord::MX { ttl, .. }
+            | DNSRecord::NS { ttl, .. }
+            | DNSRecord::TXT { ttl, .. }
+            | DNSRecord::SRV { ttl, .. }
+            | DNSRecord::SOA { ttl, .. }
+            | DNSRecord::PTR { ttl, .. }
+            | DNSRecord::DS { ttl, .. }
+            | DNSRecord::RRSIG { ttl, .. }
+            | DNSRecord::NSEC { ttl, .. }
+            | DNSRecord::DNSKEY { ttl, .. }
+            | DNSRecord::NSEC3 { ttl, .. }
+            | DNSRecord::NSEC3PARAM { ttl, .. }
+            | DNSRecord::UNKNOWN { ttl, .. } => *ttl = new_ttl,
+            DNSRecord::OPT { .. } | DNSRecord::TSIG { .. } => {}
+        }
+    }
+
+    // Same owner, type and data; the TTL and the case of the owner are not compared
+    pub fn same_data(&self, other: &DNSRecord) -> bool {
+        let mut a = self.clone();
+        let mut b = other.clone();
+        a.set_ttl(0);
+        b.set_ttl(0);
+        a == b
+    }
+
     pub fn get_ttl(self) -> u32 {
         match self {
             DNSRecord::A { domain: _, addr: _, ttl } => ttl,
@@ -293,24 +581,67 @@ impl DNSRecord {
             DNSRecord::CNAME { domain: _, host: _, ttl } => ttl,
             DNSRecord::MX { domain: _, priority: _, host: _, ttl } => ttl,
             DNSRecord::NS { domain: _, host: _, ttl } => ttl,
-            DNSRecord::UNKNOWN { domain: _, qtype: _, data_len: _, ttl } => ttl,
+            DNSRecord::TXT { domain: _, data: _, ttl } => ttl,
+            DNSRecord::SRV { ttl, .. } => ttl,
+            DNSRecord::OPT { .. } | DNSRecord::TSIG { .. } => 0,
+            DNSRecord::SOA { ttl, .. } => ttl,
+            DNSRecord::DS { ttl, .. }
+            | DNSRecord::RRSIG { ttl, .. }
+            | DNSRecord::NSEC { ttl, .. }
+            | DNSRecord::DNSKEY { ttl, .. }
+            | DNSRecord::NSEC3 { ttl, .. }
+            | DNSRecord::NSEC3PARAM { ttl, .. } => ttl,
+            DNSRecord::PTR { domain: _, host: _, ttl } => ttl,
+            DNSRecord::UNKNOWN { domain: _, qtype: _, class: _, data: _, ttl } => ttl,
         }
     }
     
-    pub fn parse_record(parser: &mut PacketParser) -> DNSRecord {
-        let domain = parser.parse_qname();
+    pub fn parse_record(parser: &mut PacketParser) -> ParseResult<DNSRecord> {
+        let domain = parser.parse_qname()?;
         // print!("Qname: {domain}");
-        let qtype_num = parser.parse_u16();
+        let qtype_num = parser.parse_u16()?;
         // print!("Qtype: {qtype_num}");
         let qtype = QueryType::get_query_type(qtype_num);
-        let _class = parser.parse_u16();
-        let ttl = parser.parse_u32();
+        let class = parser.parse_u16()?;
+        let ttl = parser.parse_u32()?;
         // print!("Ttl: {ttl}");
-        let data_length = parser.parse_u16();
+        let data_length = parser.parse_u16()?;
 
-        match qtype {
+        // Empty RDATA of the classes ANY and NONE, in the prerequisites and deletions of UPDATE messages
+        // Empty RDATA of other classes gets the length checks of its type
+        if data_length == 0 && (class == 254 || class == 255) && qtype != QueryType::OPT {
+            return Ok(DNSRecord::UNKNOWN { domain, qtype: qtype_num, class, data: Vec::new(), ttl });
+        }
+
+        // The RDATA has to end exactly where its length says
+        let end_position = parser.position + data_length as usize;
+        let record = DNSRecord::parse_rdata(parser, domain, qtype_num, class, ttl, data_length)?;
+        if parser.position != end_position {
+            return Err(ParseError::BadRdLength { qtype: qtype_num, rdlength: data_length });
+        }
+
+        // Only IN records have their own variants: records of other classes
+        // (like the ANY and NONE of UPDATE messages) are kept as UNKNOWN, with uncompressed RDATA
+        Ok(match record {
+            DNSRecord::OPT { .. } | DNSRecord::TSIG { .. } | DNSRecord::UNKNOWN { .. } => record,
+            _ if class != 1 => DNSRecord::UNKNOWN {
+                domain: record.clone().get_domain().unwrap_or_default(),
+                qtype: qtype_num,
+                class,
+                data: record.get_rdata(),
+                ttl,
+            },
+            _ => record,
+        })
+    }
+
+    fn parse_rdata(parser: &mut PacketParser, domain: Name, qtype_num: u16, class: u16, ttl: u32, data_length: u16) -> ParseResult<DNSRecord> {
+        let qtype = QueryType::get_query_type(qtype_num);
+        let end_position = parser.position + data_length as usize;
+        let bad_rdlength = ParseError::BadRdLength { qtype: qtype_num, rdlength: data_length };
+        let record = match qtype {
             QueryType::A => {
-                let raw_address = parser.parse_u32();
+                let raw_address = parser.parse_u32()?;
                 let address = Ipv4Addr::new(
                     ((raw_address >> 24) & 0xFF) as u8,
                     ((raw_address >> 16) & 0xFF) as u8,
@@ -325,10 +656,10 @@ impl DNSRecord {
                 }
             }
             QueryType::AAAA => {
-                let raw_addr1 = parser.parse_u32();
-                let raw_addr2 = parser.parse_u32();
-                let raw_addr3= parser.parse_u32();
-                let raw_addr4 = parser.parse_u32();
+                let raw_addr1 = parser.parse_u32()?;
+                let raw_addr2 = parser.parse_u32()?;
+                let raw_addr3= parser.parse_u32()?;
+                let raw_addr4 = parser.parse_u32()?;
                 let addr = Ipv6Addr::new(
                     ((raw_addr1 >> 16) & 0xFFFF) as u16,
                     ((raw_addr1 >> 0) & 0xFFFF) as u16,
@@ -347,7 +678,7 @@ impl DNSRecord {
                 }
             }
             QueryType::NS => {
-                let ns = parser.parse_qname();
+                let ns = parser.parse_qname()?;
 
                 DNSRecord::NS {
                     domain: domain,
@@ -357,7 +688,7 @@ impl DNSRecord {
 
             }
             QueryType::CNAME => {
-                let cname = parser.parse_qname();
+                let cname = parser.parse_qname()?;
 
                 DNSRecord::CNAME {
                     domain: domain, 
@@ -365,9 +696,39 @@ impl DNSRecord {
                     ttl: ttl,
                 }
             }
+            QueryType::PTR => {
+                let ptr = parser.parse_qname()?;
+
+                DNSRecord::PTR {
+                    domain,
+                    host: ptr,
+                    ttl,
+                }
+            }
+            QueryType::SOA => {
+                let mname = parser.parse_qname()?;
+                let rname = parser.parse_qname()?;
+                let serial = parser.parse_u32()?;
+                let refresh = parser.parse_u32()?;
+                let retry = parser.parse_u32()?;
+                let expire = parser.parse_u32()?;
+                let minimum = parser.parse_u32()?;
+
+                DNSRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    ttl,
+                }
+            }
             QueryType::MX => {
-                let priority = parser.parse_u16();
-                let mx = parser.parse_qname();
+                let priority = parser.parse_u16()?;
+                let mx = parser.parse_qname()?;
 
                 DNSRecord::MX {
                     domain: domain, 
@@ -376,17 +737,225 @@ impl DNSRecord {
                     ttl: ttl,
                 }
             }
-            QueryType::UNKNOWN(_) => {
-                parser.jump(data_length as usize);
+            QueryType::OPT => {
+                // The TTL field holds the extended RCODE, the version and the flags
+                let mut options = Vec::new();
+
+                while parser.position < end_position {
+                    let code = parser.parse_u16()?;
+                    let length = parser.parse_u16()?;
+                    let data = parser.parse_bytes(length as usize)?;
+                    options.push(EdnsOption { code, data });
+                }
+
+                DNSRecord::OPT {
+                    packet_len: class,
+                    ext_rcode: (ttl >> 24) as u8,
+                    version: ((ttl >> 16) & 0xFF) as u8,
+                    dnssec_ok: (ttl & 0x8000) > 0,
+                    flags: (ttl & 0x7FFF) as u16,
+                    options,
+                }
+            }
+            QueryType::TSIG => {
+                // Names in the RDATA of TSIG are never compressed
+                let algorithm = parser.parse_qname()?;
+                let time_high = parser.parse_u16()? as u64;
+                let time_low = parser.parse_u32()? as u64;
+                let fudge = parser.parse_u16()?;
+                let mac_size = parser.parse_u16()?;
+                let mac = parser.parse_bytes(mac_size as usize)?;
+                let original_id = parser.parse_u16()?;
+                let error = parser.parse_u16()?;
+                let other_len = parser.parse_u16()?;
+                let other = parser.parse_bytes(other_len as usize)?;
+
+                DNSRecord::TSIG {
+                    domain,
+                    algorithm,
+                    time_signed: (time_high << 32) | time_low,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other,
+                }
+            }
+            QueryType::SRV => {
+                let priority = parser.parse_u16()?;
+                let weight = parser.parse_u16()?;
+                let port = parser.parse_u16()?;
+                let srv = parser.parse_qname()?;
+
+                DNSRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host: srv,
+                    ttl,
+                }
+            }
+            QueryType::DS => {
+                let key_tag = parser.parse_u16()?;
+                let algorithm = parser.parse_byte()?;
+                let digest_type = parser.parse_byte()?;
+                let digest = parser.parse_bytes(end_position.checked_sub(parser.position).ok_or(bad_rdlength)?)?;
+
+                DNSRecord::DS {
+                    domain,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest,
+                    ttl,
+                }
+            }
+            QueryType::RRSIG => {
+                let type_covered = parser.parse_u16()?;
+                let algorithm = parser.parse_byte()?;
+                let labels = parser.parse_byte()?;
+                let original_ttl = parser.parse_u32()?;
+                let expiration = parser.parse_u32()?;
+                let inception = parser.parse_u32()?;
+                let key_tag = parser.parse_u16()?;
+                let signer = parser.parse_qname()?;
+                let signature = parser.parse_bytes(end_position.checked_sub(parser.position).ok_or(bad_rdlength)?)?;
+
+                DNSRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature,
+                    ttl,
+                }
+            }
+            QueryType::NSEC => {
+                let next = parser.parse_qname()?;
+                let bitmap = parser.parse_bytes(end_position.checked_sub(parser.position).ok_or(bad_rdlength)?)?;
+
+                DNSRecord::NSEC {
+                    domain,
+                    next,
+                    types: decode_type_bitmap(&bitmap)?,
+                    ttl,
+                }
+            }
+            QueryType::DNSKEY => {
+                let flags = parser.parse_u16()?;
+                let protocol = parser.parse_byte()?;
+                let algorithm = parser.parse_byte()?;
+                let public_key = parser.parse_bytes(end_position.checked_sub(parser.position).ok_or(bad_rdlength)?)?;
+
+                DNSRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    ttl,
+                }
+            }
+            QueryType::NSEC3 => {
+                let hash_algorithm = parser.parse_byte()?;
+                let flags = parser.parse_byte()?;
+                let iterations = parser.parse_u16()?;
+                let salt_length = parser.parse_byte()?;
+                let salt = parser.parse_bytes(salt_length as usize)?;
+                let hash_length = parser.parse_byte()?;
+                let next_hashed = parser.parse_bytes(hash_length as usize)?;
+                let bitmap = parser.parse_bytes(end_position.checked_sub(parser.position).ok_or(bad_rdlength)?)?;
+
+                DNSRecord::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed,
+                    types: decode_type_bitmap(&bitmap)?,
+                    ttl,
+                }
+            }
+            QueryType::NSEC3PARAM => {
+                let hash_algorithm = parser.parse_byte()?;
+                let flags = parser.parse_byte()?;
+                let iterations = parser.parse_u16()?;
+                let salt_length = parser.parse_byte()?;
+                let salt = parser.parse_bytes(salt_length as usize)?;
+
+                DNSRecord::NSEC3PARAM {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    ttl,
+                }
+            }
+            QueryType::TXT => {
+                // RDATA is a sequence of <length><bytes> character-strings
+                let mut data = Vec::new();
+
+                while parser.position < end_position {
+                    let length = parser.parse_byte()?;
+                    data.push(parser.parse_bytes(length as usize)?);
+                }
+
+                DNSRecord::TXT {
+                    domain,
+                    data,
+                    ttl,
+                }
+            }
+            // Meta types like AXFR are not expected in records: keep their RDATA as it is
+            QueryType::UNKNOWN(_) | QueryType::IXFR | QueryType::AXFR => {
+                let data = parser.parse_bytes(data_length as usize)?;
 
                 DNSRecord::UNKNOWN {
-                    domain: domain, 
+                    domain,
                     qtype: qtype_num,
-                    data_len: data_length, 
-                    ttl: ttl,
+                    class,
+                    data,
+                    ttl,
                 }
             }
-        }
+        };
+
+        Ok(record)
+    }
+
+    // RDATA of the record in wire format, without name compression
+    pub fn get_rdata(&self) -> Vec<u8> {
+        let mut writer = PacketWriter::new();
+        let size = self.write_record(&mut writer);
+
+        let mut name_writer = PacketWriter::new();
+        name_writer.write_qname(&self.clone().get_domain().unwrap_or_default());
+        // Name, then type, class, TTL and RDATA length
+        let start = name_writer.position() + 10;
+
+        writer.buffer[start..size].to_vec()
+    }
+
+    // Build an IN record from its RDATA in wire format
+    pub fn from_rdata(domain: &Name, qtype: u16, ttl: u32, data: &[u8]) -> ParseResult<DNSRecord> {
+        let mut writer = PacketWriter::new();
+        writer.write_qname(domain);
+        writer.write_u16(qtype);
+        writer.write_u16(1);
+        writer.write_u32(ttl);
+        writer.write_u16(data.len() as u16);
+        writer.write_bytes(data);
+
+        let mut parser = PacketParser::from_bytes(&writer.buffer[..writer.position()]);
+        DNSRecord::parse_record(&mut parser)
     }
 
     pub fn write_record(&self, writer: &mut PacketWriter) -> usize {
@@ -398,7 +967,7 @@ impl DNSRecord {
                 ref addr,
                 ttl,
             } => {
-                writer.write_qname(domain);
+                writer.write_name(domain);
                 writer.write_u16(QueryType::A.to_num());
                 writer.write_u16(1);
                 writer.write_u32(ttl);
@@ -415,7 +984,7 @@ impl DNSRecord {
                 ref host, 
                 ttl 
             } => {
-                writer.write_qname(domain);
+                writer.write_name(domain);
                 writer.write_u16(QueryType::NS.to_num());
                 writer.write_u16(1);
                 writer.write_u32(ttl);
@@ -423,7 +992,7 @@ impl DNSRecord {
                 let pos = writer.position();
                 writer.write_u16(0);
 
-                writer.write_qname(host);
+                writer.write_name(host);
 
                 let size = writer.position() - (pos + 2);
                 writer.set_u16(pos, size as u16);
@@ -433,7 +1002,7 @@ impl DNSRecord {
                 ref host,
                 ttl,
             } => {
-                writer.write_qname(domain);
+                writer.write_name(domain);
                 writer.write_u16(QueryType::CNAME.to_num());
                 writer.write_u16(1);
                 writer.write_u32(ttl);
@@ -441,7 +1010,55 @@ impl DNSRecord {
                 let pos = writer.position();
                 writer.write_u16(0);
                 
-                writer.write_qname(host);
+                writer.write_name(host);
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::PTR {
+                ref domain,
+                ref host,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::PTR.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                writer.write_name(host);
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::SOA {
+                ref domain,
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::SOA.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                writer.write_name(mname);
+                writer.write_name(rname);
+                writer.write_u32(serial);
+                writer.write_u32(refresh);
+                writer.write_u32(retry);
+                writer.write_u32(expire);
+                writer.write_u32(minimum);
 
                 let size = writer.position() - (pos + 2);
                 writer.set_u16(pos, size as u16);
@@ -452,7 +1069,7 @@ impl DNSRecord {
                 ref host, 
                 ttl, 
             } => {
-                writer.write_qname(domain);
+                writer.write_name(domain);
                 writer.write_u16(QueryType::MX.to_num());
                 writer.write_u16(1);
                 writer.write_u32(ttl);
@@ -461,9 +1078,29 @@ impl DNSRecord {
                 writer.write_u16(0);
                 
                 writer.write_u16(priority);
-                writer.write_qname(host);
+                writer.write_name(host);
 
-                let size = writer.position();
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::TXT {
+                ref domain,
+                ref data,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::TXT.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                for string in data {
+                    writer.write_character_string(string);
+                }
+
+                let size = writer.position() - (pos + 2);
                 writer.set_u16(pos, size as u16);
             }
             DNSRecord::AAAA {
@@ -471,7 +1108,7 @@ impl DNSRecord {
                 ref addr, 
                 ttl,
             } => {
-                writer.write_qname(domain);
+                writer.write_name(domain);
                 writer.write_u16(QueryType::AAAA.to_num());
                 writer.write_u16(1);
                 writer.write_u32(ttl);
@@ -481,8 +1118,250 @@ impl DNSRecord {
                     writer.write_u16(*octet);
                 }
             }
-            DNSRecord::UNKNOWN { .. } => {
-                println!("Skipping record: {:?}", self);
+            DNSRecord::SRV {
+                ref domain,
+                priority,
+                weight,
+                port,
+                ref host,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::SRV.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                writer.write_u16(priority);
+                writer.write_u16(weight);
+                writer.write_u16(port);
+                // The target is never compressed (RFC 2782)
+                writer.write_qname(host);
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::OPT {
+                packet_len,
+                ext_rcode,
+                version,
+                dnssec_ok,
+                flags,
+                ref options,
+            } => {
+                // Owner name is always the root
+                writer.write_qname(&Name::root());
+                writer.write_u16(QueryType::OPT.to_num());
+                writer.write_u16(packet_len);
+                writer.write_u8(ext_rcode);
+                writer.write_u8(version);
+                writer.write_u16(((dnssec_ok as u16) << 15) | (flags & 0x7FFF));
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                for option in options {
+                    writer.write_u16(option.code);
+                    writer.write_u16(option.data.len() as u16);
+                    writer.write_bytes(&option.data);
+                }
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::DS {
+                ref domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::DS.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+                writer.write_u16(4 + digest.len() as u16);
+
+                writer.write_u16(key_tag);
+                writer.write_u8(algorithm);
+                writer.write_u8(digest_type);
+                writer.write_bytes(digest);
+            }
+            DNSRecord::RRSIG {
+                ref domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer,
+                ref signature,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::RRSIG.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                writer.write_u16(type_covered);
+                writer.write_u8(algorithm);
+                writer.write_u8(labels);
+                writer.write_u32(original_ttl);
+                writer.write_u32(expiration);
+                writer.write_u32(inception);
+                writer.write_u16(key_tag);
+                // Names in DNSSEC RDATA are never compressed (RFC 4034 section 3.1.7)
+                writer.write_qname(signer);
+                writer.write_bytes(signature);
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::NSEC {
+                ref domain,
+                ref next,
+                ref types,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::NSEC.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                // Names in DNSSEC RDATA are never compressed (RFC 4034 section 4.1.1)
+                writer.write_qname(next);
+                writer.write_bytes(&encode_type_bitmap(types));
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::DNSKEY {
+                ref domain,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::DNSKEY.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+                writer.write_u16(4 + public_key.len() as u16);
+
+                writer.write_u16(flags);
+                writer.write_u8(protocol);
+                writer.write_u8(algorithm);
+                writer.write_bytes(public_key);
+            }
+            DNSRecord::NSEC3 {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ref next_hashed,
+                ref types,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::NSEC3.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                writer.write_u8(hash_algorithm);
+                writer.write_u8(flags);
+                writer.write_u16(iterations);
+                writer.write_u8(salt.len() as u8);
+                writer.write_bytes(salt);
+                writer.write_u8(next_hashed.len() as u8);
+                writer.write_bytes(next_hashed);
+                writer.write_bytes(&encode_type_bitmap(types));
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::NSEC3PARAM {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(QueryType::NSEC3PARAM.to_num());
+                writer.write_u16(1);
+                writer.write_u32(ttl);
+                writer.write_u16(5 + salt.len() as u16);
+
+                writer.write_u8(hash_algorithm);
+                writer.write_u8(flags);
+                writer.write_u16(iterations);
+                writer.write_u8(salt.len() as u8);
+                writer.write_bytes(salt);
+            }
+            DNSRecord::TSIG {
+                ref domain,
+                ref algorithm,
+                time_signed,
+                fudge,
+                ref mac,
+                original_id,
+                error,
+                ref other,
+            } => {
+                // Class ANY and TTL 0, names never compressed (RFC 8945 section 4.2)
+                writer.write_qname(domain);
+                writer.write_u16(QueryType::TSIG.to_num());
+                writer.write_u16(255);
+                writer.write_u32(0);
+
+                let pos = writer.position();
+                writer.write_u16(0);
+
+                writer.write_qname(algorithm);
+                writer.write_u16((time_signed >> 32) as u16);
+                writer.write_u32(time_signed as u32);
+                writer.write_u16(fudge);
+                writer.write_u16(mac.len() as u16);
+                writer.write_bytes(mac);
+                writer.write_u16(original_id);
+                writer.write_u16(error);
+                writer.write_u16(other.len() as u16);
+                writer.write_bytes(other);
+
+                let size = writer.position() - (pos + 2);
+                writer.set_u16(pos, size as u16);
+            }
+            DNSRecord::UNKNOWN {
+                ref domain,
+                qtype,
+                class,
+                ref data,
+                ttl,
+            } => {
+                writer.write_name(domain);
+                writer.write_u16(qtype);
+                writer.write_u16(class);
+                writer.write_u32(ttl);
+                writer.write_u16(data.len() as u16);
+
+                writer.write_bytes(data);
             }
         }
 
@@ -490,6 +1369,175 @@ impl DNSRecord {
     }
 }
 
+// Types listed in the type bitmap of NSEC and NSEC3 records (RFC 4034 section 4.1.2)
+// Window blocks hold 1 to 32 bytes and come in increasing window order
+pub fn decode_type_bitmap(data: &[u8]) -> ParseResult<Vec<u16>> {
+    let mut types = Vec::new();
+    let mut pos = 0;
+    let mut last_window = None;
+    while pos < data.len() {
+        let bad_block = ParseError::BadTypeBitmap { offset: pos };
+        if pos + 2 > data.len() {
+            return Err(bad_block);
+        }
+        let window = data[pos] as u16;
+        let length = data[pos + 1] as usize;
+        if length == 0 || length > 32 || pos + 2 + length > data.len() || last_window.is_some_and(|last| window <= last) {
+            return Err(bad_block);
+        }
+        for (i, byte) in data[pos + 2..pos + 2 + length].iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(window * 256 + (i * 8 + bit) as u16);
+                }
+            }
+        }
+        last_window = Some(window);
+        pos += 2 + length;
+    }
+    Ok(types)
+}
+
+// Type bitmap of a set of types: one block per window of 256 types, without trailing zero bytes
+pub fn encode_type_bitmap(types: &[u16]) -> Vec<u8> {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+
+    let mut data = Vec::new();
+    let mut block: Option<(u8, Vec<u8>)> = None;
+    for qtype in types {
+        let window = (qtype >> 8) as u8;
+        let offset = (qtype & 0xFF) as usize;
+        if block.as_ref().is_some_and(|(w, _)| *w != window) {
+            let (w, bits) = block.take().unwrap();
+            data.push(w);
+            data.push(bits.len() as u8);
+            data.extend_from_slice(&bits);
+        }
+        let (_, bits) = block.get_or_insert((window, Vec::new()));
+        if bits.len() <= offset / 8 {
+            bits.resize(offset / 8 + 1, 0);
+        }
+        bits[offset / 8] |= 0x80 >> (offset % 8);
+    }
+    if let Some((w, bits)) = block {
+        data.push(w);
+        data.push(bits.len() as u8);
+        data.extend_from_slice(&bits);
+    }
+    data
+}
+
+// Presentation format for TXT character-strings
+// Every string is quoted; quotes, backslashes and non-printable bytes are escaped
+pub fn character_strings_to_text(strings: &[Vec<u8>]) -> String {
+    let mut quoted = Vec::new();
+
+    for string in strings {
+        let mut text = "\"".to_owned();
+        for &byte in string {
+            match byte {
+                b'"' | b'\\' => {
+                    text.push('\\');
+                    text.push(byte as char);
+                }
+                0x20..=0x7E => text.push(byte as char),
+                _ => text.push_str(&format!("\\{:03}", byte)),
+            }
+        }
+        text.push('"');
+        quoted.push(text);
+    }
+
+    quoted.join(" ")
+}
+
+// Generic presentation format for RDATA of unknown types (RFC 3597)
+// \# <length> <hex data>
+pub fn rdata_to_generic_text(data: &[u8]) -> String {
+    let mut text = format!("\\# {}", data.len());
+    if !data.is_empty() {
+        text.push(' ');
+        for byte in data {
+            text.push_str(&format!("{:02x}", byte));
+        }
+    }
+
+    text
+}
+
+// ________________________________________________ REVERSE LOOKUP _______________________________________________________
+// Build the in-addr.arpa name for an IPv4 address
+// 192.0.2.1 => 1.2.0.192.in-addr.arpa
+pub fn reverse_name_v4(addr: Ipv4Addr) -> Name {
+    let mut labels: Vec<Vec<u8>> = addr.octets().iter().rev()
+        .map(|octet| octet.to_string().into_bytes())
+        .collect();
+    labels.push(b"in-addr".to_vec());
+    labels.push(b"arpa".to_vec());
+    // 6 short labels, always within the limits
+    Name::from_labels(labels).unwrap()
+}
+
+// Build the ip6.arpa name for an IPv6 address
+// One label per nibble, least significant nibble first
+pub fn reverse_name_v6(addr: Ipv6Addr) -> Name {
+    let mut labels = Vec::new();
+    for octet in addr.octets().iter().rev() {
+        labels.push(format!("{:x}", octet & 0x0F).into_bytes());
+        labels.push(format!("{:x}", octet >> 4).into_bytes());
+    }
+    labels.push(b"ip6".to_vec());
+    labels.push(b"arpa".to_vec());
+    // 34 labels and 73 bytes, always within the limits
+    Name::from_labels(labels).unwrap()
+}
+
+// Build the reverse lookup name for any IP address
+pub fn reverse_name(addr: IpAddr) -> Name {
+    match addr {
+        IpAddr::V4(v4) => reverse_name_v4(v4),
+        IpAddr::V6(v6) => reverse_name_v6(v6),
+    }
+}
+
+// Get the IP address back from an in-addr.arpa or ip6.arpa name
+// Returns None for names that are not complete reverse names:
+// in-addr.arpa labels are 1 to 3 ASCII digits, ip6.arpa labels a single hex digit
+pub fn parse_reverse_name(name: &Name) -> Option<IpAddr> {
+    let labels = name.labels();
+    let has_suffix = |suffix: &[&[u8]]| labels.len() > suffix.len()
+        && labels[labels.len() - suffix.len()..].iter().zip(suffix).all(|(label, expected)| label.eq_ignore_ascii_case(expected));
+
+    if labels.len() == 6 && has_suffix(&[b"in-addr", b"arpa"]) {
+        let mut octets = [0u8; 4];
+        for (i, label) in labels[..4].iter().rev().enumerate() {
+            if label.len() > 3 || !label.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            octets[i] = std::str::from_utf8(label).ok()?.parse().ok()?;
+        }
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+
+    if labels.len() == 34 && has_suffix(&[b"ip6", b"arpa"]) {
+        let nibbles = labels[..32].iter()
+            .map(|label| match label.as_slice() {
+                [digit] => (*digit as char).to_digit(16).map(|nibble| nibble as u8),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>()?;
+        let mut octets = [0u8; 16];
+        for (i, pair) in nibbles.chunks(2).rev().enumerate() {
+            octets[i] = (pair[1] << 4) | pair[0];
+        }
+        return Some(IpAddr::V6(Ipv6Addr::from(octets)));
+    }
+
+    None
+}
+
 // ________________________________________________ PACKET _______________________________________________________________
 
 // DNS Packet
@@ -500,6 +1548,8 @@ pub struct DNSPacket {
     pub answers: Vec<DNSRecord>,
     pub authorities: Vec<DNSRecord>,
     pub resources: Vec<DNSRecord>,
+    // Compress the names when writing the packet; on by default
+    pub compression: bool,
 }
 
 impl DNSPacket {
@@ -510,36 +1560,63 @@ impl DNSPacket {
             answers: Vec::new(),
             authorities: Vec::new(),
             resources: Vec::new(),
+            compression: true,
         }
     }
 
-    pub fn get_dns_packet(parser: &mut PacketParser) -> DNSPacket {
+    pub fn get_dns_packet(parser: &mut PacketParser) -> ParseResult<DNSPacket> {
         let mut dns_packet = DNSPacket::new();
 
-        dns_packet.header.parse_header(parser);
+        dns_packet.header.parse_header(parser)?;
 
         for _ in 0..dns_packet.header.qd_count {
             let mut question = DNSQuestion::new();
-            question.parse_question(parser);
+            question.parse_question(parser)?;
             dns_packet.questions.push(question);
         }
 
         for _ in 0..dns_packet.header.an_count {
-            let answer = DNSRecord::parse_record(parser);
+            let answer = DNSRecord::parse_record(parser)?;
             dns_packet.answers.push(answer);
         }
 
         for _ in 0..dns_packet.header.ns_count {
-            let record  = DNSRecord::parse_record(parser);
+            let record  = DNSRecord::parse_record(parser)?;
             dns_packet.authorities.push(record);
         }
 
         for _ in 0..dns_packet.header.ar_count {
-            let record  = DNSRecord::parse_record(parser);
+            let record  = DNSRecord::parse_record(parser)?;
             dns_packet.resources.push(record);
         }
 
-        dns_packet
+        // Combine the extended RCODE bits of the OPT record with the 4 bits of the header
+        if let Some(&DNSRecord::OPT { ext_rcode, .. }) = dns_packet.get_opt() {
+            let rcode = ((ext_rcode as u16) << 4) | (dns_packet.header.rcode.to_num() & 0x0F);
+            dns_packet.header.rcode = RCode::get_rcode(rcode);
+        }
+
+        Ok(dns_packet)
+    }
+
+    // FORMERR answer to a message that could not be parsed (RFC 1035 section 4.1.1)
+    // Messages too short for a header and responses are not answered
+    pub fn format_error(parser: &PacketParser) -> Option<DNSPacket> {
+        let mut header_parser = PacketParser::from_bytes(&parser.buffer[..parser.length.min(12)]);
+        let mut request = DNSHeader::new();
+        request.parse_header(&mut header_parser).ok()?;
+        if request.query {
+            return None;
+        }
+
+        let mut packet = DNSPacket::new();
+        packet.header.id = request.id;
+        packet.header.opcode = request.opcode;
+        packet.header.recursion_desired = request.recursion_desired;
+        packet.header.recursion_available = true;
+        packet.header.query = true;
+        packet.header.rcode = RCode::FORMERR;
+        Some(packet)
     }
 
     pub fn write_dns_packet(&mut self, writer: &mut PacketWriter) {
@@ -548,6 +1625,15 @@ impl DNSPacket {
         self.header.ns_count = self.authorities.len() as u16;
         self.header.ar_count = self.resources.len() as u16;
 
+        // The upper RCODE bits go into the OPT record
+        let ext_rcode = (self.header.rcode.to_num() >> 4) as u8;
+        for rec in self.resources.iter_mut() {
+            if let DNSRecord::OPT { ext_rcode: ref mut opt_rcode, .. } = *rec {
+                *opt_rcode = ext_rcode;
+            }
+        }
+
+        writer.compression = self.compression;
         self.header.write_header(writer);
 
         for question in &self.questions {
@@ -564,6 +1650,38 @@ impl DNSPacket {
         }
     }
 
+    // Write the packet for sending over UDP, in at most max_size bytes
+    // Additional records are dropped first (OPT is kept);
+    // if it still does not fit, the answer and authority sections are emptied and TC is set
+    pub fn write_udp_packet(&mut self, writer: &mut PacketWriter, max_size: usize) {
+        self.write_dns_packet(writer);
+        if writer.position() <= max_size {
+            return;
+        }
+
+        self.resources.retain(|record| matches!(record, DNSRecord::OPT { .. }));
+        *writer = PacketWriter::new();
+        self.write_dns_packet(writer);
+        if writer.position() <= max_size {
+            return;
+        }
+
+        self.answers.clear();
+        self.authorities.clear();
+        self.header.truncation = true;
+        *writer = PacketWriter::new();
+        self.write_dns_packet(writer);
+    }
+
+    // Largest UDP response the sender of this packet accepts
+    // 512 bytes, or the EDNS(0) payload size if it is larger
+    pub fn get_max_udp_size(&self) -> usize {
+        match self.get_opt() {
+            Some(&DNSRecord::OPT { packet_len, .. }) => (packet_len as usize).max(512),
+            _ => 512,
+        }
+    }
+
     // Get a random A record from a packet
     pub fn get_random_record(&self) -> Option<Ipv4Addr> {
         self.answers.iter().filter_map(|record| match record {
@@ -572,18 +1690,30 @@ impl DNSPacket {
         }).next()
     }
 
+    // Get the OPT pseudo-record from the additional section
+    pub fn get_opt(&self) -> Option<&DNSRecord> {
+        self.resources.iter().find(|record| matches!(record, DNSRecord::OPT { .. }))
+    }
+
+    // Get the SOA record from the authorities section
+    // Used for negative caching of NXDOMAIN and NODATA answers
+    pub fn get_soa(&self) -> Option<&DNSRecord> {
+        self.authorities.iter().find(|record| matches!(record, DNSRecord::SOA { .. }))
+    }
+
     // Get iterator over all NS in the authorities section
     // tuple (domain, host)
-    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+    // Only zones containing qname, label by label
+    fn get_ns<'a>(&'a self, qname: &'a Name) -> impl Iterator<Item = (&'a Name, &'a Name)> {
         self.authorities.iter().filter_map(|record| match record{
-            DNSRecord::NS {domain, host, ..} => Some((domain.as_str(), host.as_str())),
+            DNSRecord::NS {domain, host, ..} => Some((domain, host)),
             _ => None,
-        }).filter(move |(domain, _)| qname.ends_with(*domain))
+        }).filter(move |(domain, _)| qname.is_subdomain_of(domain))
     }
 
 
     // Get the IP address for and NS record
-    pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
+    pub fn get_resolved_ns(&self, qname: &Name) -> Option<Ipv4Addr> {
         self.get_ns(qname).flat_map(|(_, host)| {
             self.resources.iter().filter_map(move |record| match record {
                 DNSRecord::A { domain, addr, .. } if domain == host => Some(addr),
@@ -593,7 +1723,7 @@ impl DNSPacket {
     }
 
     // Get the name for an NS record
-    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
+    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a Name) -> Option<&'a Name> {
         self.get_ns(qname).map(|(_, host)| host).next()
     }
 
@@ -607,13 +1737,28 @@ impl DNSPacket {
                 println!("{:#?}", q);
             }
             for rec in &self.answers {
-                println!("{:#?}", rec);
+                DNSPacket::print_record(rec);
             }   
             for rec in &self.authorities {
-                println!("{:#?}", rec);
+                DNSPacket::print_record(rec);
             }
             for rec in &self.resources {
-                println!("{:#?}", rec);
+                DNSPacket::print_record(rec);
             }
     }
+
+    // Print a record
+    // TXT data is shown as quoted text instead of raw byte lists
+    // Unknown RDATA is shown in the RFC 3597 generic format
+    fn print_record(rec: &DNSRecord) {
+        match rec {
+            DNSRecord::TXT { domain, data, ttl } => {
+                println!("TXT {{\n    domain: {:?},\n    data: {},\n    ttl: {},\n}}", domain, character_strings_to_text(data), ttl);
+            }
+            DNSRecord::UNKNOWN { domain, qtype, class, data, ttl } => {
+                println!("UNKNOWN {{\n    domain: {:?},\n    qtype: TYPE{},\n    class: CLASS{},\n    data: {},\n    ttl: {},\n}}", domain, qtype, class, rdata_to_generic_text(data), ttl);
+            }
+            _ => println!("{:#?}", rec),
+        }
+    }
 }
\ No newline at end of file
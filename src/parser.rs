@@ -1,50 +1,98 @@
-// use std::error::Error;
+use std::{fmt, io::{self, Read}, net::TcpStream,};
+
+use crate::name::Name;
+
+// Longest name on the wire, length bytes included (RFC 1035 section 2.3.4)
+const MAX_NAME_LENGTH: usize = 255;
+
+// Error while reading a packet from the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    // Reading past the end of the received data
+    Truncated { position: usize },
+    // Label length byte with the reserved 01 or 10 prefix
+    BadLabel { position: usize, length: u8 },
+    // Compression pointer that does not point to an earlier name
+    PointerLoop { position: usize },
+    // Name longer than 255 bytes
+    NameTooLong { position: usize },
+    // RDATA not matching its RDLENGTH
+    BadRdLength { qtype: u16, rdlength: u16 },
+    // NSEC or NSEC3 type bitmap with a malformed or out of order window block
+    BadTypeBitmap { offset: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Truncated { position } => write!(f, "packet truncated at byte {}", position),
+            ParseError::BadLabel { position, length } => write!(f, "bad label length {:#04x} at byte {}", length, position),
+            ParseError::PointerLoop { position } => write!(f, "compression pointer loop at byte {}", position),
+            ParseError::NameTooLong { position } => write!(f, "name longer than {} bytes at byte {}", MAX_NAME_LENGTH, position),
+            ParseError::BadRdLength { qtype, rdlength } => write!(f, "RDATA of type {} does not match its length {}", qtype, rdlength),
+            ParseError::BadTypeBitmap { offset } => write!(f, "bad type bitmap block at byte {} of the bitmap", offset),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
 
-use std::{fmt::Error, io::Read, net::TcpStream,};
+// Clients reading answers report malformed packets as invalid data
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
 
-// This is a Packet Parser for UDP Packets of 512bytes 
+pub type ParseResult<T> = Result<T, ParseError>;
+
+// This is a Packet Parser for DNS messages received over UDP or TCP
+// Only the first length bytes of the buffer hold the received message
 pub struct PacketParser {
     pub buffer: [u8; 63000], // 63000 for TCP // 4096 for UDP
     pub position: usize,
+    pub length: usize,
 }
 
 impl PacketParser {
-    
-    // New PacketParser 
-    // -> stores a packet of max 512 bytes 
+
+    // New PacketParser
+    // -> empty; the received bytes go in the buffer and their count in length
     // -> sets parsing position to 0
     pub fn new() -> PacketParser {
         PacketParser {
             buffer: [0; 63000], // 63000 for TCP // 4096 for UDP
             position: 0,
+            length: 0,
         }
     }
-    
-    pub fn tcp_stream_to_bytes(&mut self, stream: &mut TcpStream) {
-        // Skip the first two bytes 
+
+    // New PacketParser holding a copy of data
+    // Data longer than the buffer is cut
+    pub fn from_bytes(data: &[u8]) -> PacketParser {
+        let mut parser = PacketParser::new();
+        let length = data.len().min(parser.buffer.len());
+        parser.buffer[..length].copy_from_slice(&data[..length]);
+        parser.length = length;
+        parser
+    }
+
+    pub fn tcp_stream_to_bytes(&mut self, stream: &mut TcpStream) -> io::Result<()> {
         // TCP packets have the length of the packet
         // written in the first 2 bytes
-        let mut skip_buffer = [0; 2];
-        let _ = stream.read_exact(&mut skip_buffer);
-    
-        // Read the remaining bytes into the buffer
-        let bytes_read = stream.read(&mut self.buffer).expect("Error reading data from TCP Stream.");
-
-        // Return an error if the bytes read are less than the array size
-        if bytes_read > self.buffer.len() {
-            panic!("Buffer size exceeded!");    
+        let mut length_buffer = [0; 2];
+        stream.read_exact(&mut length_buffer)?;
+        let length = ((length_buffer[0] as usize) << 8) | (length_buffer[1] as usize);
+
+        if length > self.buffer.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Buffer size exceeded!"));
         }
 
+        // Read the whole packet into the buffer
+        stream.read_exact(&mut self.buffer[0..length])?;
+        self.length = length;
+        Ok(())
     }
-    // Get current parsing position
-    // fn position(&self) -> usize {
-    //     self.position
-    // }
-
-    // // Make a step of size step_size while parsing
-    // fn step(&mut self, step_size: usize) {
-    //     self.position += step_size;
-    // }
 
     // Change position to a given one
     pub fn jump(&mut self, new_position: usize) {
@@ -52,10 +100,10 @@ impl PacketParser {
     }
 
     // Read 1 byte and move the position
-    pub fn parse_byte(&mut self) -> Result<u8, Error> {
+    pub fn parse_byte(&mut self) -> ParseResult<u8> {
 
         // Get the coresponding byte content in the buffer
-        let parsed_byte = self.buffer[self.position]; 
+        let parsed_byte = self.get_byte(self.position)?;
 
         // Move position to the next byte
         self.position += 1;
@@ -64,136 +112,122 @@ impl PacketParser {
     }
 
     // Read 1 byte without moving the position
-    fn get_byte(&mut self, given_position: usize) -> Result<u8, Error> {
-
-        // Get the coresponding byte content in the buffer
-        let parsed_byte = self.buffer[given_position]; 
+    fn get_byte(&self, given_position: usize) -> ParseResult<u8> {
+        if given_position >= self.length {
+            return Err(ParseError::Truncated { position: given_position });
+        }
 
-        Ok(parsed_byte)
+        Ok(self.buffer[given_position])
     }
 
     // Parse a range of bytes
-    pub fn parse_byte_range(&mut self, start_position: usize, length: usize) -> Result<String, Error> {
-        // Check if the range overflows the packet buffer
-        if start_position + length >= 512 {
-            panic!("Not enough bytes in packet")
+    pub fn parse_byte_range(&mut self, start_position: usize, length: usize) -> ParseResult<Vec<u8>> {
+        // Check if the range overflows the received data
+        if start_position + length > self.length {
+            return Err(ParseError::Truncated { position: self.length });
         }
-        let mut name = "".to_owned();
 
-        for pos in start_position..start_position + length {
-            let parsed_byte = self.get_byte(pos).expect("Cannot parse byte.");
-            let letter = parsed_byte as char;
-            let mut letter_as_string = [0; 2];
+        Ok(self.buffer[start_position..start_position + length].to_vec())
+    }
 
-            let result = letter.encode_utf8(&mut letter_as_string);
-            name.push_str(result);
+    // Parse length bytes as raw data; Move position length steps
+    pub fn parse_bytes(&mut self, length: usize) -> ParseResult<Vec<u8>> {
+        if self.position + length > self.length {
+            return Err(ParseError::Truncated { position: self.length });
         }
+        let bytes = self.buffer[self.position..self.position + length].to_vec();
+        self.position += length;
 
-        Ok(name)
-        // let mut ascii_bytes = [0; length];
-        // ascii_bytes = self.buffer[start_position..start_position + length as usize];
-        // Ok(String::from_utf8_lossy(&ascii_bytes).to_lowercase())
+        Ok(bytes)
     }
 
     // Parse 2 bytes; Move position 2 steps
-    pub fn parse_u16(&mut self) -> u16 {
-        let parsed_bytes = ((self.parse_byte().expect("u16 parse error") as u16) << 8) 
-                        | (self.parse_byte().expect("u16 parse error") as u16);
+    pub fn parse_u16(&mut self) -> ParseResult<u16> {
+        let parsed_bytes = ((self.parse_byte()? as u16) << 8)
+                        | (self.parse_byte()? as u16);
 
-        parsed_bytes
+        Ok(parsed_bytes)
     }
 
     // Parse 4 bytes; Move position 4 steps
-    pub fn parse_u32(&mut self) -> u32 {
-        let parsed_bytes = ((self.parse_byte().expect("u32 parse error") as u32) << 24)
-                        | ((self.parse_byte().expect("u32 parse error") as u32) << 16)
-                        | ((self.parse_byte().expect("u32 parse error") as u32) << 8)
-                        | ((self.parse_byte().expect("u32 parse error") as u32) << 0);
+    pub fn parse_u32(&mut self) -> ParseResult<u32> {
+        let parsed_bytes = ((self.parse_byte()? as u32) << 24)
+                        | ((self.parse_byte()? as u32) << 16)
+                        | ((self.parse_byte()? as u32) << 8)
+                        | (self.parse_byte()? as u32);
 
-        parsed_bytes
+        Ok(parsed_bytes)
     }
 
 
     // Read the queried names
-    pub fn parse_qname(&mut self) -> String {
+    // Compression pointers must point before the place they are read at,
+    // so following them always ends (RFC 1035 section 4.1.4)
+    pub fn parse_qname(&mut self) -> ParseResult<Name> {
         // Query Name Format: ...[length]Label...
-        let mut outstr = "".to_owned();
-        // // Position variable to parse within the name
+        let mut labels = Vec::new();
+        // Position variable to parse within the name
         let mut current_position = self.position;
-        // print!("Start qname at: {}", self.position);
+        // Lowest position read so far; pointers have to go below it
+        let mut lowest_position = self.position;
+        // Length of the name on the wire, with the length bytes
+        let mut name_length = 0;
 
         // Jumps
         let mut jumped = false;
-        let max_jumps = 5;
-        let mut jumps_performed = 0;
-
-        // Get a delimiter between labels: "."
-        let mut delimiter = ""; 
 
         loop {
-            // Check if the number of jumbs exceeds the maximum
-            if jumps_performed > max_jumps {
-                panic!("Limit of {} jumps exceeded", max_jumps);
-            }
-            // print!("Current position: {current_position}");
-
             // Start reading
             // Get label length first
-            let label_length = self.get_byte(current_position).expect("get_byte error");
-            // print!("Label Length: {}", label_length);
+            let label_length = self.get_byte(current_position)?;
 
-            // If the most significant bit is set
+            // If the two most significant bits are set
             // => it is a jump to some other offset in the packet
             if (label_length & 0xC0) == 0xC0 {
+                let second_byte = self.get_byte(current_position + 1)? as u16;
                 if !jumped {
-                    self.jump(self.position + 2);
+                    self.jump(current_position + 2);
                 }
-                // print!("It's a jumping scenario!!!");
-            
 
-                let second_byte = self.get_byte(current_position + 1).expect("get_byte error") as u16;
-                let offset = (((label_length as u16) ^ 0xC0) << 8) | second_byte;
-                current_position = offset as usize;
+                let offset = ((((label_length as u16) ^ 0xC0) << 8) | second_byte) as usize;
+                if offset >= lowest_position {
+                    return Err(ParseError::PointerLoop { position: current_position });
+                }
+                current_position = offset;
+                lowest_position = offset;
 
                 // Jump performed
                 jumped = true;
-                jumps_performed += 1;
 
                 continue;
             }
-            else {
-                // Move position by one byte to start reading the actual label
-                current_position += 1;
-
-                // Last label is empty => length = 0
-                // Stop reading at this point
-                if label_length == 0 {
-                    if !jumped {
-                        self.jump(self.position + 1);
-                    }
-                    // print!("Stops qname at: {}", self.position);
-                    return outstr
-                }
+            // The 01 and 10 prefixes are reserved
+            if (label_length & 0xC0) != 0 {
+                return Err(ParseError::BadLabel { position: current_position, length: label_length });
+            }
 
-                // Append delimiter to the output name
-                // The first delimiter will be empty
-                // The rest of the delimiters will be "."
-                outstr.push_str(delimiter);
+            name_length += label_length as usize + 1;
+            if name_length > MAX_NAME_LENGTH {
+                return Err(ParseError::NameTooLong { position: current_position });
+            }
 
-                // Get the ASCII bytes for the label
-                
-                let parsed_name = self.parse_byte_range(current_position, (label_length) as usize).expect("parse_byte_range error");
-                outstr.push_str(&parsed_name);
+            // Move position by one byte to start reading the actual label
+            current_position += 1;
 
-                // Modify the delimiter
-                delimiter = ".";
-                
-                current_position += label_length as usize;
+            // Last label is empty => length = 0
+            // Stop reading at this point
+            if label_length == 0 {
+                if !jumped {
+                    self.jump(current_position);
+                }
+                // The lengths were checked while reading
+                return Name::from_labels(labels).map_err(|_| ParseError::NameTooLong { position: current_position });
             }
 
-        if !jumped {
-            self.jump(current_position);
+            // Get the bytes of the label, kept as they are
+            labels.push(self.parse_byte_range(current_position, label_length as usize)?);
+
+            current_position += label_length as usize;
         }
     }
 }
-}
\ No newline at end of file
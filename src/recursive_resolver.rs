@@ -1,10 +1,45 @@
 use std::net::Ipv4Addr;
+use crate::dnssec::{DnssecConfig, Security, Validator};
+use crate::name::Name;
 use crate::packet::{DNSPacket, QueryType, RCode};
-use crate::stub_resolver::lookup;
+use crate::stub_resolver::{lookup, lookup_dnssec};
 
 
 
-pub fn recursive_lookup(qname: &str, qtype: QueryType, rd_flag: bool) -> DNSPacket {
+pub fn recursive_lookup(qname: &Name, qtype: QueryType, rd_flag: bool) -> DNSPacket {
+    iterative_lookup(qname, qtype, rd_flag, false)
+}
+
+// Recursive lookup checking the answer with DNSSEC (RFC 4035 section 5)
+// Secure answers get the AD bit, bogus ones become SERVFAIL
+// With checking_disabled the answer is passed on without validation
+pub fn validating_lookup(qname: &Name, qtype: QueryType, rd_flag: bool, config: &DnssecConfig, checking_disabled: bool) -> DNSPacket {
+    let mut response = iterative_lookup(qname, qtype, rd_flag, true);
+    if checking_disabled {
+        return response;
+    }
+
+    let lookup = |name: &Name, qtype: QueryType| iterative_lookup(name, qtype, rd_flag, true);
+    let mut validator = Validator::new(config, &lookup);
+    match validator.validate(qname, qtype, &response) {
+        Security::Secure => {
+            println!("DNSSEC: secure answer for {}", qname);
+            response.header.authed_data = true;
+        }
+        Security::Insecure => println!("DNSSEC: insecure answer for {}", qname),
+        security => {
+            println!("DNSSEC: {:?} answer for {}", security, qname);
+            let mut failure = DNSPacket::new();
+            failure.header.rcode = RCode::SERVFAIL;
+            return failure;
+        }
+    }
+    response
+}
+
+// Follow the referrals from the root servers
+// With dnssec_ok the queries set the DO bit
+fn iterative_lookup(qname: &Name, qtype: QueryType, rd_flag: bool, dnssec_ok: bool) -> DNSPacket {
     // Set starting root server
     let mut ns = "198.41.0.4".parse::<Ipv4Addr>().unwrap();
 
@@ -15,7 +50,20 @@ pub fn recursive_lookup(qname: &str, qtype: QueryType, rd_flag: bool) -> DNSPack
         // Launch query
         let ns_copy = ns;
         let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, server, rd_flag);
+        let result = if dnssec_ok {
+            lookup_dnssec(qname, qtype, server, rd_flag)
+        } else {
+            lookup(qname, qtype, server, rd_flag)
+        };
+        let response = match result {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Lookup of {} at ns {} failed: {:?}", qname, ns, e);
+                let mut response = DNSPacket::new();
+                response.header.rcode = RCode::SERVFAIL;
+                return response;
+            }
+        };
 
 
 
@@ -38,12 +86,12 @@ pub fn recursive_lookup(qname: &str, qtype: QueryType, rd_flag: bool) -> DNSPack
         // Resolve the IP of a NS record
         // If there are no NS records, go with the latest answer
         let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(x) => x,
+            Some(x) => x.clone(),
             None => return response,
         };
 
         // Start another recursion
-        let recursive_response = recursive_lookup(&new_ns_name, QueryType::A, rd_flag);
+        let recursive_response = iterative_lookup(&new_ns_name, QueryType::A, rd_flag, false);
 
         // Pick another IP and continue looping
         if let Some(new_ns) = recursive_response.get_random_record() {
@@ -1,6 +1,17 @@
-use std::{net::Ipv4Addr, sync::Arc};
+use std::sync::Arc;
 
-use crate::{cache::Cache, packet::{DNSPacket, QueryType, RCode}, recursive_resolver::recursive_lookup, server_config::{ResolveType, ServerContext}, stub_resolver::lookup};
+use crate::{cache::{order_srv_records, NegativeType}, name::Name, packet::{DNSPacket, DNSRecord, QueryType, RCode}, forward_resolver::forward_lookup, recursive_resolver::{recursive_lookup, validating_lookup}, dnssec::strip_dnssec_records, server_config::{ResolveType, ServerContext}, tsig::find_key};
+
+// Answer to a request with an opcode we do not know (RFC 1035 section 4.1.1)
+pub fn not_implemented(request: &DNSPacket) -> DNSPacket {
+    let mut packet = DNSPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.query = true;
+    packet.header.opcode = request.header.opcode;
+    packet.header.rcode = RCode::NOTIMP;
+    packet.questions = request.questions.clone();
+    packet
+}
 
 // Handles an incoming packet
 pub fn handle_query(mut request: DNSPacket, mut server_context: Arc<ServerContext>) -> DNSPacket {
@@ -10,49 +21,132 @@ pub fn handle_query(mut request: DNSPacket, mut server_context: Arc<ServerContex
     packet.header.recursion_available = true;
     packet.header.query = true;
 
+    // Only EDNS version 0 is supported (RFC 6891)
+    if let Some(&DNSRecord::OPT { version, .. }) = request.get_opt() {
+        if version > 0 {
+            packet.questions.append(&mut request.questions);
+            packet.header.rcode = RCode::BADVERS;
+            packet.resources.push(DNSRecord::new_opt(false));
+            return packet;
+        }
+    }
+
+    // Clients setting the DO bit get the DNSSEC records of the answers
+    let dnssec_ok = matches!(request.get_opt(), Some(&DNSRecord::OPT { dnssec_ok: true, .. }));
+
     if let Some(question) = request.questions.pop() {
         println!("Received query: {:?}", question);
 
-        if let Some(some_result) = &server_context.cache.lookup(&question.qname, question.qtype) {
+        if question.qtype == QueryType::AXFR || question.qtype == QueryType::IXFR {
+            // Zone transfers are only served over TCP, by the TCP server
+            packet.questions.push(question.clone());
+            packet.header.rcode = RCode::REFUSED;
+        }
+        else if let Some(result) = server_context.authority.query(&question.qname, question.qtype, dnssec_ok) {
+            // We are authoritative for this name: answer from the zone data
+            println!("Authoritative answer for {:?}", &question.qname);
+
+            packet.questions.push(question.clone());
+            packet.header.rcode = result.header.rcode;
+            packet.header.authoritative_answer = result.header.authoritative_answer;
+            packet.answers = result.answers;
+            packet.authorities = result.authorities;
+            packet.resources = result.resources;
+        }
+        else if let Some(some_result) = &server_context.cache.lookup(&question.qname, question.qtype) {
             println!("Cache hit! for {:?}", &question.qname);
-            let result = some_result.clone();
+            let mut result = some_result.clone();
+            if !dnssec_ok {
+                strip_dnssec_records(&mut result, question.qtype);
+            }
 
             packet.questions.push(question.clone());
             packet.header.rcode = result.header.rcode;
+            // The cache keeps whether the answer was validated, the AD bit is set as for a resolved answer
+            packet.header.authed_data = result.header.authed_data && (dnssec_ok || request.header.authed_data);
+            packet.header.checking_disabled = request.header.checking_disabled;
 
-            for record in result.answers {
-                println!("Answer: {:?}", record);
-                packet.answers.push(record);
-            }
-            for record in result.authorities {
-                println!("Authority: {:?}", record);
-                packet.authorities.push(record);
+            packet.answers = result.answers;
+            if server_context.srv_ordering && question.qtype == QueryType::SRV {
+                order_srv_records(&mut packet.answers);
             }
+            packet.authorities = result.authorities;
 
             for record in result.resources {
-                println!("Resource: {:?}", record);
+                // OPT and TSIG are hop-by-hop, the upstream ones are never passed on
+                if let DNSRecord::OPT { .. } | DNSRecord::TSIG { .. } = record {
+                    continue;
+                }
                 packet.resources.push(record);
             }
         }
-        else if let result = resolve(&question.qname, question.qtype, server_context.clone()) {
+        else if let Some(mut result) = aggressive_lookup(&question.qname, question.qtype, &server_context) {
+            // Validated NSEC or NSEC3 records in the cache cover the name (RFC 8198)
+            let stats = server_context.cache.denial_stats();
+            println!("{:?} for {:?} from the NSEC cache ({} NXDOMAIN and {} NODATA answers so far)",
+                result.header.rcode, &question.qname, stats.nxdomain, stats.nodata);
+
+            packet.questions.push(question.clone());
+            packet.header.rcode = result.header.rcode;
+            packet.header.authed_data = dnssec_ok || request.header.authed_data;
+            packet.header.checking_disabled = request.header.checking_disabled;
+            if !dnssec_ok {
+                strip_dnssec_records(&mut result, question.qtype);
+            }
+            packet.authorities = result.authorities;
+        }
+        else if let mut result = resolve(&question.qname, question.qtype, dnssec_ok, request.header.checking_disabled, server_context.clone()) {
             packet.questions.push(question.clone());
             packet.header.rcode = result.header.rcode;
+            let soa = result.get_soa().cloned();
+
+            // The AD bit is only set for clients that know about DNSSEC (RFC 6840 section 5.7)
+            packet.header.authed_data = result.header.authed_data && (dnssec_ok || request.header.authed_data);
+            packet.header.checking_disabled = request.header.checking_disabled;
+            if !dnssec_ok {
+                strip_dnssec_records(&mut result, question.qtype);
+            }
+
+            packet.answers = result.answers;
 
-            for record in result.answers {
-                println!("Answer: {:?}", record);
-                packet.answers.push(record);
+            // Answers that were not validated are not shared with other clients
+            let unchecked = request.header.checking_disabled && server_context.dnssec.validate;
+            if !unchecked {
+                server_context.cache.store(&packet.answers, result.header.authed_data);
+                println!("Answers cached for {:?}", &question.qname);
             }
 
-            &server_context.cache.store(&packet.answers);
-            println!("Answers cached for {:?}", &question.qname);
+            // Validated denial records can answer for other names too (RFC 8198)
+            let negative = packet.header.rcode == RCode::NXDOMAIN
+                || (packet.header.rcode == RCode::NOERROR && packet.answers.is_empty() && soa.is_some());
+            if negative && result.header.authed_data && !unchecked && uses_validation(&question.qname, &server_context) {
+                server_context.cache.store_denial(&result.authorities);
+            }
 
-            for record in result.authorities {
-                println!("Authority: {:?}", record);
-                packet.authorities.push(record);
+            // Negative caching (RFC 2308) based on the SOA in the authority section
+            // After a CNAME chain the negative answer is about its last target (RFC 2308 section 5)
+            if let (Some(soa), false) = (soa, unchecked) {
+                let last_name = last_cname_target(&question.qname, &packet.answers);
+                let has_data = packet.answers.iter()
+                    .any(|record| record.clone().get_domain().is_some_and(|domain| domain == last_name));
+                let has_answer = packet.answers.iter()
+                    .any(|record| record.clone().get_query_type() == question.qtype);
+                if packet.header.rcode == RCode::NXDOMAIN && !has_data {
+                    server_context.cache.store_negative(&last_name, question.qtype, NegativeType::NxDomain, &soa, result.header.authed_data);
+                    println!("NXDOMAIN cached for {:?}", &last_name);
+                } else if packet.header.rcode == RCode::NOERROR && !has_answer {
+                    server_context.cache.store_negative(&last_name, question.qtype, NegativeType::NoData, &soa, result.header.authed_data);
+                    println!("NODATA cached for {:?}", &last_name);
+                }
             }
 
+            packet.authorities = result.authorities;
+
             for record in result.resources {
-                println!("Resource: {:?}", record);
+                // OPT and TSIG are hop-by-hop, the upstream ones are never passed on
+                if let DNSRecord::OPT { .. } | DNSRecord::TSIG { .. } = record {
+                    continue;
+                }
                 packet.resources.push(record);
             }
         } else {
@@ -62,21 +156,62 @@ pub fn handle_query(mut request: DNSPacket, mut server_context: Arc<ServerContex
         // Send FORMERR RCODE if a question is not present
         packet.header.rcode = RCode::FORMERR;
     }
+
+    // Answer with an OPT record only if the request had one
+    if let Some(&DNSRecord::OPT { dnssec_ok, .. }) = request.get_opt() {
+        packet.resources.push(DNSRecord::new_opt(dnssec_ok));
+    }
     return packet;
 }
 
-pub fn resolve(qname: &str, qtype: QueryType, server_context: Arc<ServerContext>) -> DNSPacket{
-    let resolver = server_context.resolve_strategy.clone();
+// The name a CNAME chain in answers ends at, starting from qname
+// The chain is followed at most once per record, so a loop ends
+fn last_cname_target(qname: &Name, answers: &[DNSRecord]) -> Name {
+    let mut name = qname.clone();
+    for _ in 0..answers.len() {
+        let target = answers.iter().find_map(|record| match record {
+            DNSRecord::CNAME { domain, host, .. } if *domain == name => Some(host.clone()),
+            _ => None,
+        });
+        match target {
+            Some(host) => name = host,
+            None => break,
+        }
+    }
+    name
+}
+
+// Names resolved recursively by the validating resolver
+fn uses_validation(qname: &Name, server_context: &ServerContext) -> bool {
+    server_context.dnssec.validate && matches!(server_context.get_resolve_strategy(qname), ResolveType::Recursive)
+}
+
+// A negative answer synthesized from the validated NSEC and NSEC3 records in the cache
+fn aggressive_lookup(qname: &Name, qtype: QueryType, server_context: &ServerContext) -> Option<DNSPacket> {
+    if !uses_validation(qname, server_context) {
+        return None;
+    }
+    server_context.cache.lookup_denial(qname, qtype)
+}
+
+pub fn resolve(qname: &Name, qtype: QueryType, dnssec_ok: bool, checking_disabled: bool, server_context: Arc<ServerContext>) -> DNSPacket{
+    let resolver = server_context.get_resolve_strategy(qname).clone();
     let rd_flag = server_context.allow_recursive;
     match resolver {
+        ResolveType::Recursive if server_context.dnssec.validate => {
+            validating_lookup(qname, qtype, rd_flag, &server_context.dnssec, checking_disabled)
+        },
         ResolveType::Recursive => {
             recursive_lookup(qname, qtype, rd_flag)
         },
-        ResolveType::Forward { host, port } => {
-            println!("Forwarding to {:?}", host);
-            let host = host.parse::<Ipv4Addr>().unwrap();
-            let server = (host, port);
-            lookup(qname, qtype, server, rd_flag)
+        ResolveType::Forward(forward_config) => {
+            // The key is looked up by name in the configured TSIG keys
+            let key = forward_config.tsig_key.as_ref()
+                .and_then(|name| find_key(&server_context.tsig_keys, name));
+            if forward_config.tsig_key.is_some() && key.is_none() {
+                println!("Unknown TSIG key {:?}, forwarding unsigned", forward_config.tsig_key);
+            }
+            forward_lookup(qname, qtype, &forward_config, &server_context.upstream_stats, rd_flag, key, dnssec_ok)
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::name::Name;
+use crate::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+use crate::server_config::{ServerContext, Upstream};
+use crate::stub_resolver::lookup_with_key;
+use crate::tsig::find_key;
+use crate::zone::Zone;
+use crate::zone_transfer::{request_axfr, request_ixfr, serial_gt};
+
+// Time between attempts while we have no SOA timers for the zone
+const DEFAULT_RETRY: Duration = Duration::from_secs(60);
+const SOA_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A zone we serve as a secondary, copied from its primary
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct SecondaryZoneConfig {
+    pub origin: Name,
+    pub primary: Upstream,
+    // Copy of the zone on disk, so a restart does not need a transfer
+    pub file: String,
+    // Name of the TSIG key signing the transfers
+    #[serde(default)]
+    pub tsig_key: Option<Name>,
+}
+
+// Copy of a secondary zone as saved on disk
+#[derive(Deserialize, Serialize)]
+struct ZoneCopy {
+    origin: Name,
+    // Last time the zone was checked against the primary, in seconds since the epoch
+    last_refresh: u64,
+    records: Vec<DNSRecord>,
+}
+
+// Channels waking up the refresh threads of the secondary zones, by origin
+// The threads stop once their channel is dropped with the server context
+#[derive(Default, Debug)]
+pub struct SecondaryZones {
+    pub notifiers: Mutex<HashMap<Name, Sender<()>>>,
+}
+
+impl Clone for SecondaryZones {
+    fn clone(&self) -> Self {
+        let notifiers_clone = self.notifiers.lock().unwrap().clone();
+        SecondaryZones {
+            notifiers: Mutex::new(notifiers_clone),
+        }
+    }
+}
+
+impl SecondaryZones {
+    pub fn new() -> SecondaryZones {
+        SecondaryZones {
+            notifiers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Ask for an immediate refresh of a zone; false if it is not one of our secondaries
+    pub fn notify(&self, origin: &Name) -> bool {
+        match self.notifiers.lock() {
+            Ok(notifiers) => notifiers.get(origin)
+                .is_some_and(|sender| sender.send(()).is_ok()),
+            Err(_) => false,
+        }
+    }
+}
+
+// SOA timers of a zone: refresh, retry and expire
+fn get_timers(zone: &Zone) -> Option<(Duration, Duration, Duration)> {
+    match zone.get_soa()? {
+        DNSRecord::SOA { refresh, retry, expire, .. } => Some((
+            Duration::from_secs(*refresh as u64),
+            Duration::from_secs(*retry as u64),
+            Duration::from_secs(*expire as u64),
+        )),
+        _ => None,
+    }
+}
+
+fn primary_address(primary: &Upstream) -> io::Result<(Ipv4Addr, u16)> {
+    match primary.host.parse::<Ipv4Addr>() {
+        Ok(host) => Ok((host, primary.port)),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+    }
+}
+
+// Load the saved copies of the secondary zones and start their refresh threads
+pub fn start_secondaries(server_context: &Arc<ServerContext>) {
+    for config in &server_context.secondary_zones {
+        let last_refresh = match load_copy(config) {
+            Ok((zone, last_refresh)) => {
+                println!("Loaded secondary zone {} from {}", zone.origin, config.file);
+                server_context.authority.insert(zone);
+                Some(last_refresh)
+            }
+            Err(e) => {
+                println!("No saved copy of secondary zone {}: {}", config.origin, e);
+                None
+            }
+        };
+
+        let (sender, receiver) = channel();
+        if let Ok(mut notifiers) = server_context.secondaries.notifiers.lock() {
+            notifiers.insert(config.origin.clone(), sender);
+        }
+
+        let config = config.clone();
+        let context = Arc::downgrade(server_context);
+        let name = format!("Secondary-{}", config.origin);
+        if let Err(e) = thread::Builder::new().name(name).spawn(move || {
+            refresh_loop(config, context, receiver, last_refresh);
+        }) {
+            println!("Error starting refresh thread: {:?}", e);
+        }
+    }
+}
+
+// Check the zone against its primary on the SOA refresh and retry timers,
+// or as soon as a NOTIFY arrives
+fn refresh_loop(config: SecondaryZoneConfig, context: Weak<ServerContext>, notify: Receiver<()>, mut last_refresh: Option<SystemTime>) {
+    // The first check is done right away
+    let mut wait = Duration::ZERO;
+    loop {
+        match notify.recv_timeout(wait) {
+            Ok(()) => println!("Refreshing secondary zone {} on NOTIFY", config.origin),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let server_context = match context.upgrade() {
+            Some(x) => x,
+            None => break,
+        };
+        let authority = &server_context.authority;
+
+        let result = refresh_zone(&config, &server_context);
+        let timers = authority.get_zone(&config.origin).as_ref().and_then(get_timers);
+        match result {
+            Ok(()) => {
+                last_refresh = Some(SystemTime::now());
+                wait = timers.map_or(DEFAULT_RETRY, |(refresh, _, _)| refresh);
+            }
+            Err(e) => {
+                println!("Refresh of secondary zone {} failed: {}", config.origin, e);
+                wait = timers.map_or(DEFAULT_RETRY, |(_, retry, _)| retry);
+
+                // Without contact to the primary for too long the data is not trusted anymore
+                if let (Some(last_refresh), Some((_, _, expire))) = (last_refresh, timers) {
+                    if last_refresh.elapsed().unwrap_or_default() > expire {
+                        println!("Secondary zone {} expired", config.origin);
+                        authority.set_expired(&config.origin);
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Stopped refreshing secondary zone {}", config.origin);
+}
+
+// Compare our serial with the primary one and transfer the zone if it changed
+// IXFR is tried first, then AXFR
+fn refresh_zone(config: &SecondaryZoneConfig, server_context: &ServerContext) -> io::Result<()> {
+    let authority = &server_context.authority;
+    let primary = primary_address(&config.primary)?;
+    let current = authority.get_zone(&config.origin);
+
+    // Queries and transfers are signed if the primary shares a key with us
+    let key = match config.tsig_key {
+        Some(ref name) => Some(find_key(&server_context.tsig_keys, name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Unknown TSIG key {}", name)))?),
+        None => None,
+    };
+
+    let response = lookup_with_key(&config.origin, QueryType::SOA, primary, false, SOA_QUERY_TIMEOUT, key)?;
+    let primary_serial = response.answers.iter()
+        .find_map(|record| match record {
+            DNSRecord::SOA { serial, .. } => Some(*serial),
+            _ => None,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No SOA in the primary answer"))?;
+
+    let zone = match current {
+        Some(ref zone) if zone.get_serial().is_some_and(|serial| !serial_gt(primary_serial, serial)) => {
+            // Up to date: only the time of the check changes
+            let mut zone = zone.clone();
+            zone.expired = false;
+            zone
+        }
+        Some(ref zone) => match request_ixfr(zone, primary, key) {
+            Ok(zone) => zone,
+            Err(e) => {
+                println!("IXFR of {} failed ({}), trying AXFR", config.origin, e);
+                request_axfr(&config.origin, primary, key)?
+            }
+        },
+        None => request_axfr(&config.origin, primary, key)?,
+    };
+
+    if zone.get_soa().is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Transferred zone has no SOA"));
+    }
+    println!("Secondary zone {} is at serial {:?}", config.origin, zone.get_serial());
+
+    if let Err(e) = save_copy(config, &zone) {
+        println!("Error saving secondary zone {}: {}", config.origin, e);
+    }
+    authority.insert(zone);
+    Ok(())
+}
+
+fn save_copy(config: &SecondaryZoneConfig, zone: &Zone) -> io::Result<()> {
+    let copy = ZoneCopy {
+        origin: zone.origin.clone(),
+        last_refresh: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        records: zone.transfer_records(),
+    };
+    let data = serde_json::to_string(&copy)?;
+    fs::write(&config.file, data)
+}
+
+// Load the saved copy of a zone and the time of its last refresh
+// A copy older than the SOA expire is loaded as expired
+fn load_copy(config: &SecondaryZoneConfig) -> io::Result<(Zone, SystemTime)> {
+    let data = fs::read_to_string(&config.file)?;
+    let copy = serde_json::from_str::<ZoneCopy>(&data)?;
+    if copy.origin != config.origin {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Saved copy is for another zone"));
+    }
+
+    let mut zone = Zone::from_records(&config.origin, copy.records);
+    let last_refresh = UNIX_EPOCH + Duration::from_secs(copy.last_refresh);
+    if let Some((_, _, expire)) = get_timers(&zone) {
+        zone.expired = last_refresh.elapsed().unwrap_or_default() > expire;
+    }
+
+    Ok((zone, last_refresh))
+}
+
+// Answer a NOTIFY message (RFC 1996)
+// Only the primary of a secondary zone may notify us of its changes
+pub fn handle_notify(request: &DNSPacket, peer: IpAddr, server_context: &ServerContext) -> DNSPacket {
+    let mut response = DNSPacket::new();
+    response.header.id = request.header.id;
+    response.header.query = true;
+    response.header.opcode = request.header.opcode;
+    response.header.authoritative_answer = true;
+    response.questions = request.questions.clone();
+
+    let question = match request.questions.first() {
+        Some(x) if x.qtype == QueryType::SOA => x,
+        _ => {
+            response.header.rcode = RCode::FORMERR;
+            return response;
+        }
+    };
+
+    let origin = &question.qname;
+    let from_primary = server_context.secondary_zones.iter()
+        .filter(|config| config.origin == *origin)
+        .any(|config| primary_address(&config.primary).is_ok_and(|(host, _)| IpAddr::V4(host) == peer));
+
+    if !from_primary || !server_context.secondaries.notify(origin) {
+        println!("Refusing NOTIFY for {} from {}", origin, peer);
+        response.header.rcode = RCode::REFUSED;
+        return response;
+    }
+
+    println!("NOTIFY for {} from {}", origin, peer);
+    response
+}
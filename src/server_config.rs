@@ -1,16 +1,64 @@
 use serde_derive::{Deserialize, Serialize};
 
 use crate::cache::{Cache, SynchronizedCache};
+use crate::dnssec::DnssecConfig;
+use crate::name::Name;
+use crate::forward_resolver::UpstreamStats;
+use crate::secondary::{SecondaryZoneConfig, SecondaryZones};
+use crate::tsig::TsigKey;
+use crate::zone::{Authority, ZoneConfig};
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    pub host: String,
+    pub port: u16,
+}
+
+// Order in which the upstreams of a forwarder are tried
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverStrategy {
+    Sequential, // as listed in the config
+    Random,
+    Fastest,    // lowest smoothed RTT first
+}
+
+// Settings of a forwarder
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ForwardConfig {
+    #[serde(rename = "upstreams")]
+    pub upstreams: Vec<Upstream>,
+    // Time to wait for each upstream answer
+    #[serde(rename = "timeout_ms", default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    // Extra rounds over all upstreams after the first one failed
+    #[serde(rename = "retries", default)]
+    pub retries: u32,
+    #[serde(rename = "failover", default = "default_failover")]
+    pub failover: FailoverStrategy,
+    // Name of the TSIG key signing the forwarded queries
+    #[serde(rename = "tsig_key", default)]
+    pub tsig_key: Option<Name>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum ResolveType {
     Recursive,
-    Forward {
-        #[serde(rename = "host")]
-        host: String,
-        #[serde(rename = "port")]
-        port: u16,
-    },
+    Forward(ForwardConfig),
+}
+
+// Resolve strategy used for the names under a zone (conditional forwarding)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ZoneResolveStrategy {
+    pub zone: Name,
+    pub resolve_strategy: ResolveType,
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_failover() -> FailoverStrategy {
+    FailoverStrategy::Sequential
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -18,12 +66,42 @@ pub struct ServerContext {
     pub dns_port: u16,
     pub dns_host: String,
     pub resolve_strategy: ResolveType,
+    // Per zone strategies; the longest matching zone wins over resolve_strategy
+    #[serde(default)]
+    pub conditional_forwarding: Vec<ZoneResolveStrategy>,
     pub allow_recursive: bool,
     pub enable_udp: bool,
     pub enable_tcp: bool,
     pub thread_count: usize,
+    // Zones we answer authoritatively, loaded from master files
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    // Zones copied from a primary server
+    #[serde(default)]
+    pub secondary_zones: Vec<SecondaryZoneConfig>,
+    // Order SRV answers by priority and weight (RFC 2782)
+    #[serde(default)]
+    pub srv_ordering: bool,
+    // Addresses and CIDR ranges allowed to transfer our zones (AXFR)
+    #[serde(default)]
+    pub allow_transfer: Vec<String>,
+    // TSIG keys allowed to transfer our zones
+    #[serde(default)]
+    pub allow_transfer_keys: Vec<Name>,
+    // Keys shared with other servers, for TSIG
+    #[serde(default)]
+    pub tsig_keys: Vec<TsigKey>,
+    // DNSSEC validation of recursive answers
+    #[serde(default)]
+    pub dnssec: DnssecConfig,
     #[serde(skip_serializing, skip_deserializing)]
     pub cache: SynchronizedCache,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub upstream_stats: UpstreamStats,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub authority: Authority,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub secondaries: SecondaryZones,
 
 }
 
@@ -34,16 +112,37 @@ impl Default for ServerContext {
 }
 
 impl ServerContext {
+    // Get the resolve strategy for a name
+    // Longest zone suffix match, on label boundaries and ignoring case
+    pub fn get_resolve_strategy(&self, qname: &Name) -> &ResolveType {
+        self.conditional_forwarding.iter()
+            .filter(|entry| qname.is_subdomain_of(&entry.zone))
+            .max_by_key(|entry| entry.zone.label_count())
+            .map(|entry| &entry.resolve_strategy)
+            .unwrap_or(&self.resolve_strategy)
+    }
+
     pub fn new() -> ServerContext {
         ServerContext {
             dns_port: 53,
             dns_host: "0.0.0.0".to_string(),
             resolve_strategy: ResolveType::Recursive,
+            conditional_forwarding: Vec::new(),
             allow_recursive: false,
             enable_udp: false,
             enable_tcp: false,
             thread_count: 1,
+            zones: Vec::new(),
+            secondary_zones: Vec::new(),
+            srv_ordering: false,
+            allow_transfer: Vec::new(),
+            allow_transfer_keys: Vec::new(),
+            tsig_keys: Vec::new(),
+            dnssec: DnssecConfig::default(),
             cache: SynchronizedCache::new(),
+            upstream_stats: UpstreamStats::new(),
+            authority: Authority::new(),
+            secondaries: SecondaryZones::new(),
         }
     }
 }
@@ -53,6 +152,15 @@ impl PartialEq for ServerContext {
         self.dns_host == other.dns_host && self.dns_port == other.dns_port 
         && self.allow_recursive == other.allow_recursive && self.enable_tcp == other.enable_tcp 
         && self.enable_udp == other.enable_udp && self.thread_count == other.thread_count
+        && self.srv_ordering == other.srv_ordering
+        && self.allow_transfer == other.allow_transfer
+        && self.allow_transfer_keys == other.allow_transfer_keys
+        && self.tsig_keys == other.tsig_keys
+        && self.dnssec == other.dnssec
+        && self.resolve_strategy == other.resolve_strategy
+        && self.conditional_forwarding == other.conditional_forwarding
+        && self.zones == other.zones
+        && self.secondary_zones == other.secondary_zones
     }
 }
 
@@ -15,7 +15,7 @@ use std::time::{Instant, Duration};
 use std::{fs, thread};
 use notify::{ RecursiveMode, Watcher, Event};
 
-use crate::{server, server_config, udp_connection};
+use crate::{secondary, server, server_config, udp_connection};
 use crate::tcp_connection::TCPServer;
 
 pub fn init_servers() -> Result<(), Box<dyn std::error::Error>> {
@@ -87,9 +87,12 @@ fn import_config() -> Result<ServerContext, notify::Error> {
     let config_path = "config/server_config.json";
     let config_data = fs::read_to_string(config_path).expect("Unable to read config file");
     let server_context = serde_json::from_str::<ServerContext>(&config_data)
-        .map_err(|e| notify::Error::generic(&e.to_string())); // Convert Box<dyn Error> to notify::Error
+        .map_err(|e| notify::Error::generic(&e.to_string()))?; // Convert Box<dyn Error> to notify::Error
 
-    server_context
+    // Load the authoritative zones listed in the config
+    server_context.authority.load(&server_context.zones);
+
+    Ok(server_context)
 }
 
 fn start_server(old_context: Arc<ServerContext>,udp_sender: Sender<()>, udp_receiver: Arc<Mutex<Receiver<()>>>, tcp_sender: Sender<()>, tcp_receiver: Arc<Mutex<Receiver<()>>>) -> Result<Arc<ServerContext>,notify::Error> {
@@ -98,27 +101,31 @@ fn start_server(old_context: Arc<ServerContext>,udp_sender: Sender<()>, udp_rece
     let udp_server_state = old_context.enable_udp;
 
 
-    if let Err(e) = import_config() {
-        // Wrong config; Keep the current config running
-        println!("Failed to import server configuration: {}", e);
-        Err(e)
-    } else {
-        // New config; Make the changes
-        let server_context = Arc::new(import_config().unwrap());
-        let context_copy = server_context.clone();
-        println!("Successfully imported server configuration: {:?}", server_context);
-
-        
-
-        // 
-        if old_context != server_context {
-            println!("Applying changes... {:?}", udp_server_state);  
-            start_udp_server(old_context.clone(), server_context.clone(), udp_server_state, udp_receiver.clone(), udp_sender);    
-            start_tcp_server(old_context.clone(), server_context.clone(), tcp_server_state, tcp_receiver.clone(), tcp_sender)
-            }
-        
+    match import_config() {
+        Err(e) => {
+            // Wrong config; Keep the current config running
+            println!("Failed to import server configuration: {}", e);
+            Err(e)
+        }
+        Ok(new_context) => {
+            // New config; Make the changes
+            let server_context = Arc::new(new_context);
+            let context_copy = server_context.clone();
+            println!("Successfully imported server configuration: {:?}", server_context);
+
+            
+
+            // 
+            if old_context != server_context {
+                println!("Applying changes... {:?}", udp_server_state);  
+                secondary::start_secondaries(&server_context);
+                start_udp_server(old_context.clone(), server_context.clone(), udp_server_state, udp_receiver.clone(), udp_sender);    
+                start_tcp_server(old_context.clone(), server_context.clone(), tcp_server_state, tcp_receiver.clone(), tcp_sender)
+                }
+            
 
-        Ok(context_copy)
+            Ok(context_copy)
+        }
     }   
 } 
 
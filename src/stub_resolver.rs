@@ -1,28 +1,177 @@
-use std::net::{Ipv4Addr, UdpSocket};
-use crate::recursive_resolver::recursive_lookup;
-use crate::{packet::{DNSPacket, DNSQuestion, QueryType, RCode}, parser::PacketParser, writer::PacketWriter};
- 
-static id: u16 = 6666;
-pub fn lookup(qname: &str, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool) -> DNSPacket{
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use rand::{rngs::OsRng, Rng};
+use crate::{name::Name, packet::{reverse_name, DNSPacket, DNSQuestion, DNSRecord, QueryType}, parser::PacketParser, tsig::{TsigContext, TsigKey}, writer::PacketWriter};
+
+// How long to wait for the answer of an upstream server
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn lookup(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool) -> io::Result<DNSPacket> {
+    lookup_with_timeout(qname, qtype, server, rd_flag, QUERY_TIMEOUT)
+}
+
+// Lookup giving up after timeout if no valid answer arrives
+pub fn lookup_with_timeout(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool, timeout: Duration) -> io::Result<DNSPacket> {
+    lookup_with_key(qname, qtype, server, rd_flag, timeout, None)
+}
+
+// Lookup with the DO bit set, so the answer carries the DNSSEC records (RFC 3225)
+pub fn lookup_dnssec(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool) -> io::Result<DNSPacket> {
+    udp_lookup(qname, qtype, server, rd_flag, QUERY_TIMEOUT, None, true)
+}
+
+// Lookup with the query signed with a TSIG key; the answer must be signed with the same key
+pub fn lookup_with_key(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool, timeout: Duration, key: Option<&TsigKey>) -> io::Result<DNSPacket> {
+    udp_lookup(qname, qtype, server, rd_flag, timeout, key, false)
+}
+
+pub fn udp_lookup(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool, timeout: Duration, key: Option<&TsigKey>, dnssec_ok: bool) -> io::Result<DNSPacket> {
 
     // Set up socket connection to server
-    let socket = UdpSocket::bind(("0.0.0.0", 43210)).expect("Couldn't connect to server");
+    // Port 0 lets the OS pick a random ephemeral source port for every query
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
 
-    // Build DNS Query Packet
-    let query = build_query(qname, qtype, rd_flag);
+    // Build DNS Query Packet with a random ID
+    let id = random_id();
+    let mut query = build_query(id, qname, qtype, rd_flag, dnssec_ok);
+    let mut tsig = key.map(|key| TsigContext::new(key.clone()));
+    if let Some(tsig) = tsig.as_mut() {
+        tsig.sign(&mut query, 0);
+    }
 
     // Send the packet
-    socket.send_to(&query.buffer[0..query.position], server).expect("Error on sending packet");
+    socket.send_to(&query.buffer[0..query.position], server)?;
+
+    // Recieve the answer
+    // Anything that does not match the query is dropped and we keep waiting until the deadline
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "No valid answer before the deadline"));
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+
+        let mut response_parser = PacketParser::new();
+        let (size, src) = match socket.recv_from(&mut response_parser.buffer) {
+            Ok(x) => x,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "No valid answer before the deadline"));
+            }
+            Err(e) => return Err(e),
+        };
+
+        if src != SocketAddr::from(server) {
+            println!("Dropping answer from unexpected source {}", src);
+            continue;
+        }
+
+        response_parser.length = size;
+
+        // Build DNSPacket
+        let packet = match DNSPacket::get_dns_packet(&mut response_parser) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Dropping malformed answer for {}: {}", qname, e);
+                continue;
+            }
+        };
+        if !is_valid_response(&packet, id, qname, qtype) {
+            println!("Dropping answer not matching query {} for {}", id, qname);
+            continue;
+        }
+        if let Some(mut tsig) = tsig.clone() {
+            if let Err(error) = tsig.verify(&response_parser, &packet) {
+                println!("Dropping answer for {} with invalid TSIG: {}", qname, error);
+                continue;
+            }
+        }
+
+        // The answer did not fit in a UDP packet; ask again over TCP
+        if packet.header.truncation {
+            println!("Truncated answer for {}, retrying over TCP", qname);
+            return tcp_lookup(qname, qtype, server, rd_flag, timeout, key, dnssec_ok);
+        }
+
+        return Ok(packet);
+    }
+}
+
+// Send the query over TCP
+// Messages are framed with their length in the first 2 bytes
+pub fn lookup_tcp(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool) -> io::Result<DNSPacket> {
+    lookup_tcp_with_timeout(qname, qtype, server, rd_flag, QUERY_TIMEOUT)
+}
+
+pub fn lookup_tcp_with_timeout(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool, timeout: Duration) -> io::Result<DNSPacket> {
+    lookup_tcp_with_key(qname, qtype, server, rd_flag, timeout, None)
+}
+
+pub fn lookup_tcp_with_key(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool, timeout: Duration, key: Option<&TsigKey>) -> io::Result<DNSPacket> {
+    tcp_lookup(qname, qtype, server, rd_flag, timeout, key, false)
+}
+
+fn tcp_lookup(qname: &Name, qtype: QueryType, server:(Ipv4Addr, u16), rd_flag:bool, timeout: Duration, key: Option<&TsigKey>, dnssec_ok: bool) -> io::Result<DNSPacket> {
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::from(server), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    // Build DNS Query Packet with a random ID
+    let id = random_id();
+    let mut query = build_query(id, qname, qtype, rd_flag, dnssec_ok);
+    let mut tsig = key.map(|key| TsigContext::new(key.clone()));
+    if let Some(tsig) = tsig.as_mut() {
+        tsig.sign(&mut query, 0);
+    }
+
+    // Send the length followed by the packet
+    let mut length_label = [0u8; 2];
+    PacketWriter::write_label_length(query.position as u16, &mut length_label);
+    let data = PacketWriter::concatenate_arrays(&length_label, &query.buffer[0..query.position]);
+    stream.write_all(&data)?;
 
     // Recieve the answer
     let mut response_parser = PacketParser::new();
-    socket.recv_from(&mut response_parser.buffer).expect("Error on receiving packet");
+    response_parser.tcp_stream_to_bytes(&mut stream)?;
 
     // Build DNSPacket
-    let packet = DNSPacket::get_dns_packet(&mut response_parser);
-    packet
+    let packet = DNSPacket::get_dns_packet(&mut response_parser)?;
+    if !is_valid_response(&packet, id, qname, qtype) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Answer does not match the query"));
+    }
+    if let Some(tsig) = tsig.as_mut() {
+        tsig.verify(&response_parser, &packet)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid TSIG: {}", error)))?;
+    }
+
+    Ok(packet)
 }
-pub fn build_query(qname: &str, qtype: QueryType, rd_flag: bool) -> PacketWriter {
+
+// Reverse lookup: query the PTR record of an IP address
+pub fn reverse_lookup(addr: IpAddr, server:(Ipv4Addr, u16), rd_flag:bool) -> io::Result<DNSPacket> {
+    let qname = reverse_name(addr);
+    lookup(&qname, QueryType::PTR, server, rd_flag)
+}
+
+// Query IDs come from the OS random number generator,
+// so they cannot be predicted by an off-path attacker
+pub fn random_id() -> u16 {
+    OsRng.gen::<u16>()
+}
+
+// An answer is only accepted if it is a response to our query:
+// same ID and the same question we asked
+pub fn is_valid_response(packet: &DNSPacket, id: u16, qname: &Name, qtype: QueryType) -> bool {
+    if !packet.header.query || packet.header.id != id || packet.questions.len() != 1 {
+        return false;
+    }
+
+    let question = &packet.questions[0];
+    question.qname == *qname
+        && question.qtype == qtype && question.class == 1
+}
+
+pub fn build_query(id: u16, qname: &Name, qtype: QueryType, rd_flag: bool, dnssec_ok: bool) -> PacketWriter {
         // Init new DNS Packet
         let mut query_packet = DNSPacket::new();
 
@@ -30,14 +179,17 @@ pub fn build_query(qname: &str, qtype: QueryType, rd_flag: bool) -> PacketWriter
         query_packet.header.id = id;
         query_packet.header.qd_count = 1;
         query_packet.header.recursion_desired = rd_flag;
-        
+
         // Set the question
         let mut question = DNSQuestion::new();
-        question.qname = qname.to_string();
+        question.qname = qname.clone();
         question.qtype = qtype;
         question.class = 1;
         query_packet.questions.push(question);
 
+        // Advertise our UDP payload size with EDNS(0)
+        query_packet.resources.push(DNSRecord::new_opt(dnssec_ok));
+
         // Write Packet
         let mut req_buffer = PacketWriter::new();
         query_packet.write_dns_packet(&mut req_buffer);
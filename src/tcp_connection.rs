@@ -1,10 +1,13 @@
 use std::{
-    io::prelude::*,
-    net::{TcpListener, TcpStream}, sync::{mpsc::{self, channel, Sender}, Arc, Mutex}, thread::{self, Builder},
+    io::{self, prelude::*, ErrorKind},
+    net::{TcpListener, TcpStream}, sync::{mpsc::{self, channel, Sender}, Arc, Mutex}, thread::{self, Builder}, time::Duration,
 };
 use rand::{Rng, thread_rng};
 
-use crate::{cache::Cache, packet::DNSPacket, parser::PacketParser, resolve_strategy, server::DNSServer, server_config::ServerContext, stub_resolver, writer::PacketWriter};
+use crate::{packet::{DNSPacket, OpCode, QueryType}, parser::PacketParser, resolve_strategy, secondary, server::DNSServer, server_config::ServerContext, tsig::{self, TsigContext, TsigStatus}, update, writer::PacketWriter, zone_transfer};
+
+// Idle connections are closed after this long without a query
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct TCPServer {
     context: Arc<ServerContext>,
@@ -53,34 +56,94 @@ impl DNSServer for TCPServer {
                         Err(_) => continue,
                     };
 
-                    // Parse the received packet
-                    let mut packet_parser = PacketParser::new();
-                    PacketParser::tcp_stream_to_bytes(&mut packet_parser, &mut stream);
-                    let request = DNSPacket::get_dns_packet(&mut packet_parser);
-                
-                    // Print packet details 
-                    DNSPacket::print_packet(&request);
-                    // panic!("Packet received!");
-                    // Get the answer for the current request by forwarding
-                    let mut response = resolve_strategy::handle_query(request, context.clone());
-
-                    
-                    // Prepare response for sending
-                    let mut response_writer = PacketWriter::new();
-                    response.write_dns_packet(&mut response_writer);
-
-                    let length = response_writer.position() as u16;
-                    let data = response_writer.get_range(0, length.into());
-                    let mut length_label = [0u8; 2];
-                    PacketWriter::write_label_length(length, &mut length_label);
-
-                    let vec_data = PacketWriter::concatenate_arrays(&length_label, data);
-                    let len = vec_data.len() + 2;
-
-                    let stream_data = PacketWriter::vec_to_array(vec_data).expect("Error converting vector to array.");
-                    // Send response
-                    let bytes_written = stream.write(&stream_data[0..len as usize]).expect("Error on sending TCP response.");
-                    // println!("Written {:?} bytes to stream.", bytes_written);
+                    let peer = match stream.peer_addr() {
+                        Ok(x) => x.ip(),
+                        Err(e) => {
+                            println!("Error getting TCP peer address: {:?}", e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)) {
+                        println!("Error setting TCP read timeout: {:?}", e);
+                        continue;
+                    }
+
+                    // A client may send several queries on the same connection (RFC 7766)
+                    'connection: loop {
+                        // Parse the received packet
+                        let mut packet_parser = PacketParser::new();
+                        if let Err(e) = PacketParser::tcp_stream_to_bytes(&mut packet_parser, &mut stream) {
+                            // The client closing the connection is the normal end
+                            if e.kind() != ErrorKind::UnexpectedEof {
+                                println!("Error reading data from TCP Stream: {:?}", e);
+                            }
+                            break;
+                        }
+                        // Malformed requests are answered with FORMERR; the framing still holds
+                        let request = match DNSPacket::get_dns_packet(&mut packet_parser) {
+                            Ok(x) => x,
+                            Err(e) => {
+                                println!("Malformed request from {}: {}", peer, e);
+                                if let Some(mut response) = DNSPacket::format_error(&packet_parser) {
+                                    let mut writer = PacketWriter::new();
+                                    response.write_dns_packet(&mut writer);
+                                    if let Err(e) = write_tcp_bytes(&mut stream, &writer) {
+                                        println!("Error on sending TCP response: {:?}", e);
+                                        break;
+                                    }
+                                }
+                                continue;
+                            }
+                        };
+
+                        // Print packet details
+                        DNSPacket::print_packet(&request);
+
+                        // Signed requests get signed responses
+                        let tsig_status = tsig::verify_request(&packet_parser, &request, &context.tsig_keys);
+                        let key_name = tsig_status.key_name().cloned();
+                        let (mut responses, mut signer) = match tsig_status {
+                            TsigStatus::Failed { error, tsig, context: signer } => {
+                                let mut response = tsig::error_response(&request, error, &tsig, signer.is_some());
+                                let mut writer = PacketWriter::new();
+                                response.write_dns_packet(&mut writer);
+                                if let Some(mut signer) = signer {
+                                    signer.sign(&mut writer, error);
+                                }
+                                if let Err(e) = write_tcp_bytes(&mut stream, &writer) {
+                                    println!("Error on sending TCP response: {:?}", e);
+                                    break;
+                                }
+                                continue;
+                            }
+                            TsigStatus::Verified(signer) => (Vec::new(), Some(signer)),
+                            TsigStatus::Unsigned => (Vec::new(), None),
+                        };
+
+                        // Zone transfers are answered with a stream of messages
+                        // IXFR is answered with the whole zone (RFC 1995 section 4)
+                        let is_transfer = request.questions.first()
+                            .is_some_and(|question| question.qtype == QueryType::AXFR || question.qtype == QueryType::IXFR);
+                        if let OpCode::UNKNOWN(_) = request.header.opcode {
+                            responses.push(resolve_strategy::not_implemented(&request));
+                        } else if request.header.opcode == OpCode::NOTIFY {
+                            responses.push(secondary::handle_notify(&request, peer, &context));
+                        } else if request.header.opcode == OpCode::UPDATE {
+                            responses.push(update::handle_update(&request, peer, key_name.as_ref(), &context));
+                        } else if is_transfer {
+                            responses = zone_transfer::handle_axfr(&request, peer, key_name.as_ref(), &context);
+                        } else {
+                            responses.push(resolve_strategy::handle_query(request, context.clone()));
+                        }
+
+                        // Send responses
+                        for mut response in responses {
+                            if let Err(e) = write_tcp_packet(&mut stream, &mut response, signer.as_mut()) {
+                                println!("Error on sending TCP response: {:?}", e);
+                                break 'connection;
+                            }
+                        }
+                    }
                 } // End inner thread loop
             }) {
                     Ok(x) => handlers.push(x),
@@ -129,4 +192,24 @@ impl DNSServer for TCPServer {
         }
         println!("TCP Server is Down.")
     }
-}
\ No newline at end of file
+}
+
+// Send a packet on a TCP stream, prefixed with its length
+// The packet is signed if a TSIG context is given
+pub fn write_tcp_packet(stream: &mut TcpStream, packet: &mut DNSPacket, tsig: Option<&mut TsigContext>) -> io::Result<()> {
+    let mut writer = PacketWriter::new();
+    packet.write_dns_packet(&mut writer);
+    if let Some(tsig) = tsig {
+        tsig.sign(&mut writer, 0);
+    }
+
+    write_tcp_bytes(stream, &writer)
+}
+
+// Send a written packet on a TCP stream, prefixed with its length
+fn write_tcp_bytes(stream: &mut TcpStream, writer: &PacketWriter) -> io::Result<()> {
+    let mut length_label = [0u8; 2];
+    PacketWriter::write_label_length(writer.position() as u16, &mut length_label);
+    let data = PacketWriter::concatenate_arrays(&length_label, &writer.buffer[0..writer.position()]);
+    stream.write_all(&data)
+}
@@ -0,0 +1,319 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::hmac;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::name::Name;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, RCode};
+use crate::parser::PacketParser;
+use crate::writer::PacketWriter;
+
+// The only algorithm supported (RFC 8945 section 6)
+pub const HMAC_SHA256: &str = "hmac-sha256";
+const SHA256_LEN: usize = 32;
+
+// Allowed clock skew between the signer and us, in seconds
+const FUDGE: u16 = 300;
+
+// TSIG error codes, sent in the TSIG record (RFC 8945 section 3)
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+
+fn default_algorithm() -> Name {
+    HMAC_SHA256.parse().unwrap()
+}
+
+// A named key shared with another server
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct TsigKey {
+    pub name: Name,
+    #[serde(default = "default_algorithm")]
+    pub algorithm: Name,
+    // Base64 encoded secret
+    pub secret: String,
+}
+
+impl TsigKey {
+    fn hmac_key(&self) -> Option<hmac::Key> {
+        if self.algorithm != HMAC_SHA256 {
+            return None;
+        }
+        let secret = STANDARD.decode(self.secret.trim()).ok()?;
+
+        Some(hmac::Key::new(hmac::HMAC_SHA256, &secret))
+    }
+}
+
+// Find a key by name, ignoring case
+pub fn find_key<'a>(keys: &'a [TsigKey], name: &Name) -> Option<&'a TsigKey> {
+    keys.iter().find(|key| key.name == *name)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Names in the MAC are in canonical form: lowercase and uncompressed
+fn write_canonical_name(data: &mut Vec<u8>, name: &Name) {
+    let mut writer = PacketWriter::new();
+    writer.write_qname(&name.to_lowercase());
+    data.extend_from_slice(&writer.buffer[..writer.position()]);
+}
+
+// State of a signed exchange
+// Each MAC covers the previous one: the request MAC for the first answer,
+// then the MAC of the previous message of a TCP stream (RFC 8945 section 5.3)
+#[derive(Clone, Debug)]
+pub struct TsigContext {
+    pub key: TsigKey,
+    prior_mac: Option<Vec<u8>>,
+    // After the first answer only the timers are covered by the MAC
+    continuation: bool,
+    // Unsigned messages of a TCP stream, covered by the next MAC
+    pending: Vec<u8>,
+}
+
+impl TsigContext {
+    pub fn new(key: TsigKey) -> TsigContext {
+        TsigContext {
+            key,
+            prior_mac: None,
+            continuation: false,
+            pending: Vec::new(),
+        }
+    }
+
+    // Size of the TSIG record added by sign
+    pub fn record_size(&self) -> usize {
+        let record = DNSRecord::TSIG {
+            domain: self.key.name.clone(),
+            algorithm: self.key.algorithm.clone(),
+            time_signed: 0,
+            fudge: FUDGE,
+            mac: vec![0; SHA256_LEN],
+            original_id: 0,
+            error: 0,
+            other: vec![0; 6],
+        };
+        record.write_record(&mut PacketWriter::new())
+    }
+
+    // Data covered by the MAC of a message (RFC 8945 section 4.3)
+    fn mac_data(&self, message: &[u8], time_signed: u64, fudge: u16, error: u16, other: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(prior_mac) = &self.prior_mac {
+            data.extend_from_slice(&(prior_mac.len() as u16).to_be_bytes());
+            data.extend_from_slice(prior_mac);
+        }
+        data.extend_from_slice(&self.pending);
+        data.extend_from_slice(message);
+
+        if !self.continuation {
+            write_canonical_name(&mut data, &self.key.name);
+            data.extend_from_slice(&255u16.to_be_bytes()); // class ANY
+            data.extend_from_slice(&0u32.to_be_bytes()); // TTL
+            write_canonical_name(&mut data, &self.key.algorithm);
+        }
+        data.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+        data.extend_from_slice(&fudge.to_be_bytes());
+        if !self.continuation {
+            data.extend_from_slice(&error.to_be_bytes());
+            data.extend_from_slice(&(other.len() as u16).to_be_bytes());
+            data.extend_from_slice(other);
+        }
+
+        data
+    }
+
+    // The next MAC covers this one
+    fn next_message(&mut self, mac: Vec<u8>) {
+        self.continuation = self.prior_mac.is_some();
+        self.prior_mac = Some(mac);
+        self.pending.clear();
+    }
+
+    // Check if unsigned messages were received since the last signed one
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Sign a message written by DNSPacket::write_dns_packet
+    // The TSIG record is added at the end of the additional section
+    pub fn sign(&mut self, writer: &mut PacketWriter, error: u16) {
+        self.sign_at(writer, error, now());
+    }
+
+    // Same with the time of signing given, in seconds since the epoch
+    pub fn sign_at(&mut self, writer: &mut PacketWriter, error: u16, time_signed: u64) {
+        let length = writer.position();
+        let original_id = u16::from_be_bytes([writer.buffer[0], writer.buffer[1]]);
+        // A BADTIME error tells our time in the other data
+        let other = if error == BADTIME { time_signed.to_be_bytes()[2..].to_vec() } else { Vec::new() };
+
+        let key = match self.key.hmac_key() {
+            Some(x) => x,
+            None => {
+                println!("Cannot sign with TSIG key {}: invalid algorithm or secret", self.key.name);
+                return;
+            }
+        };
+        let data = self.mac_data(&writer.buffer[..length], time_signed, FUDGE, error, &other);
+        let mac = hmac::sign(&key, &data).as_ref().to_vec();
+
+        let record = DNSRecord::TSIG {
+            domain: self.key.name.clone(),
+            algorithm: self.key.algorithm.clone(),
+            time_signed,
+            fudge: FUDGE,
+            mac: mac.clone(),
+            original_id,
+            error,
+            other,
+        };
+        record.write_record(writer);
+
+        let ar_count = u16::from_be_bytes([writer.buffer[10], writer.buffer[11]]) + 1;
+        writer.set_u16(10, ar_count);
+        self.next_message(mac);
+    }
+
+    // Check the TSIG of a message read by DNSPacket::get_dns_packet
+    // Unsigned messages are only accepted inside a TCP stream
+    pub fn verify(&mut self, parser: &PacketParser, packet: &DNSPacket) -> Result<(), u16> {
+        self.verify_at(parser, packet, now())
+    }
+
+    // Same with our time given, in seconds since the epoch
+    pub fn verify_at(&mut self, parser: &PacketParser, packet: &DNSPacket, now: u64) -> Result<(), u16> {
+        let tsig = match packet.resources.last() {
+            Some(record @ DNSRecord::TSIG { .. }) => record,
+            _ if self.continuation => {
+                self.pending.extend_from_slice(&parser.buffer[..parser.position]);
+                return Ok(());
+            }
+            _ => return Err(BADSIG),
+        };
+        let (name, algorithm, time_signed, fudge, mac, original_id, error, other) = match tsig {
+            DNSRecord::TSIG { domain, algorithm, time_signed, fudge, mac, original_id, error, other } => (domain, algorithm, *time_signed, *fudge, mac, *original_id, *error, other),
+            _ => return Err(BADSIG),
+        };
+        if *name != self.key.name || *algorithm != self.key.algorithm {
+            return Err(BADKEY);
+        }
+
+        // The MAC covers the message as it was before the TSIG was added
+        let start = tsig_position(parser).ok_or(BADSIG)?;
+        let mut message = parser.buffer[..start].to_vec();
+        message[0..2].copy_from_slice(&original_id.to_be_bytes());
+        let ar_count = u16::from_be_bytes([message[10], message[11]]) - 1;
+        message[10..12].copy_from_slice(&ar_count.to_be_bytes());
+
+        let key = self.key.hmac_key().ok_or(BADKEY)?;
+        let data = self.mac_data(&message, time_signed, fudge, error, other);
+        if hmac::verify(&key, &data, mac).is_err() {
+            return Err(BADSIG);
+        }
+
+        self.next_message(mac.clone());
+        if now.abs_diff(time_signed) > fudge as u64 {
+            return Err(BADTIME);
+        }
+        Ok(())
+    }
+}
+
+// Start of the last record of the message, where the TSIG is
+fn tsig_position(parser: &PacketParser) -> Option<usize> {
+    let mut copy = PacketParser::from_bytes(&parser.buffer[..parser.position]);
+
+    let packet = DNSPacket::get_dns_packet(&mut copy).ok()?;
+    let records = packet.answers.len() + packet.authorities.len() + packet.resources.len();
+    if records == 0 {
+        return None;
+    }
+
+    copy.position = 12;
+    for _ in 0..packet.questions.len() {
+        DNSQuestion::new().parse_question(&mut copy).ok()?;
+    }
+    for _ in 0..records - 1 {
+        DNSRecord::parse_record(&mut copy).ok()?;
+    }
+    Some(copy.position)
+}
+
+// TSIG check of a received request
+#[derive(Clone, Debug)]
+pub enum TsigStatus {
+    Unsigned,
+    Verified(TsigContext),
+    // The answer is NOTAUTH with the error in its TSIG
+    // The context is there if the answer can still be signed (BADTIME)
+    Failed { error: u16, tsig: DNSRecord, context: Option<TsigContext> },
+}
+
+impl TsigStatus {
+    // Name of the key that signed the request
+    pub fn key_name(&self) -> Option<&Name> {
+        match self {
+            TsigStatus::Verified(context) => Some(&context.key.name),
+            _ => None,
+        }
+    }
+}
+
+// Check the TSIG of a request with the keys we know
+pub fn verify_request(parser: &PacketParser, request: &DNSPacket, keys: &[TsigKey]) -> TsigStatus {
+    let tsig = match request.resources.last() {
+        Some(record @ DNSRecord::TSIG { .. }) => record.clone(),
+        _ => return TsigStatus::Unsigned,
+    };
+    let key_name = tsig.clone().get_domain().unwrap_or_default();
+
+    let key = match find_key(keys, &key_name) {
+        Some(x) => x,
+        None => {
+            println!("Request signed with unknown TSIG key {}", key_name);
+            return TsigStatus::Failed { error: BADKEY, tsig, context: None };
+        }
+    };
+
+    let mut context = TsigContext::new(key.clone());
+    match context.verify(parser, request) {
+        Ok(()) => TsigStatus::Verified(context),
+        Err(BADTIME) => TsigStatus::Failed { error: BADTIME, tsig, context: Some(context) },
+        Err(error) => {
+            println!("TSIG verification with key {} failed: {}", key_name, error);
+            TsigStatus::Failed { error, tsig, context: None }
+        }
+    }
+}
+
+// Answer to a request whose TSIG failed (RFC 8945 section 5.2)
+// Without a usable key the TSIG of the answer has no MAC
+pub fn error_response(request: &DNSPacket, error: u16, tsig: &DNSRecord, signed: bool) -> DNSPacket {
+    let mut response = DNSPacket::new();
+    response.header.id = request.header.id;
+    response.header.query = true;
+    response.header.opcode = request.header.opcode;
+    response.header.rcode = RCode::NOTAUTH;
+    response.questions = request.questions.clone();
+
+    if !signed {
+        if let DNSRecord::TSIG { domain, algorithm, time_signed, fudge, .. } = tsig {
+            response.resources.push(DNSRecord::TSIG {
+                domain: domain.clone(),
+                algorithm: algorithm.clone(),
+                time_signed: *time_signed,
+                fudge: *fudge,
+                mac: Vec::new(),
+                original_id: request.header.id,
+                error,
+                other: Vec::new(),
+            });
+        }
+    }
+    response
+}
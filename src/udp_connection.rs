@@ -1,12 +1,12 @@
 use std::{collections::VecDeque, io, net::{SocketAddr, UdpSocket}, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::Duration};
 
-use crate::{cache::Cache, packet::DNSPacket, parser::PacketParser, resolve_strategy, server::DNSServer, server_config, stub_resolver};
+use crate::{packet::{DNSPacket, OpCode}, parser::PacketParser, resolve_strategy, secondary, server::DNSServer, tsig::{self, TsigStatus}, update};
 use crate::writer::PacketWriter;
 use crate::server_config::ServerContext;
 pub struct UDPServer {
     // Using Arc to share ownership between multiple threads
     context: Arc<ServerContext>,
-    request_queue: Arc<Mutex<VecDeque<(SocketAddr, DNSPacket)>>>,
+    request_queue: Arc<Mutex<VecDeque<(SocketAddr, DNSPacket, TsigStatus)>>>,
     request_cond: Arc<Condvar>,
     workers: Vec<JoinHandle<()>>,
     receiver: Arc<Mutex<mpsc::Receiver<()>>>,
@@ -65,7 +65,7 @@ impl DNSServer for UDPServer {
                     }
                     // println!("Working...thread = {:?}", thread_id);
                     // Take request from queue only if lock is aquired
-                    let (src, request) = match request_queue.lock().ok()
+                    let (src, request, tsig_status) = match request_queue.lock().ok()
                                         .and_then(|mut x| x.pop_front()) {
                                             Some(x) => x,
                                             None => {
@@ -79,12 +79,38 @@ impl DNSServer for UDPServer {
                     // DNSPacket::print_packet(&request);
 
 
+                    // Largest response the client accepts over UDP
+                    let max_size = request.get_max_udp_size();
+
+                    // Signed requests get signed responses
+                    let key_name = tsig_status.key_name().cloned();
+                    let (mut response, mut signer, tsig_error) = match tsig_status {
+                        TsigStatus::Failed { error, tsig, context: signer } => {
+                            (tsig::error_response(&request, error, &tsig, signer.is_some()), signer, error)
+                        }
+                        TsigStatus::Verified(signer) => (DNSPacket::new(), Some(signer), 0),
+                        TsigStatus::Unsigned => (DNSPacket::new(), None, 0),
+                    };
+
                     // Get the answer for the current request by forwarding
-                    let mut response = resolve_strategy::handle_query(request, context.clone());
+                    if tsig_error == 0 {
+                        response = match request.header.opcode {
+                            OpCode::NOTIFY => secondary::handle_notify(&request, src.ip(), &context),
+                            OpCode::UPDATE => update::handle_update(&request, src.ip(), key_name.as_ref(), &context),
+                            OpCode::UNKNOWN(_) => resolve_strategy::not_implemented(&request),
+                            _ => resolve_strategy::handle_query(request, context.clone()),
+                        };
+                    }
                     
                     // Prepare response for sendng
+                    // Oversized responses are truncated and get the TC flag
+                    // Room is left for the TSIG record
                     let mut response_writer = PacketWriter::new();
-                    response.write_dns_packet(&mut response_writer);
+                    let tsig_size = signer.as_ref().map_or(0, |signer| signer.record_size());
+                    response.write_udp_packet(&mut response_writer, max_size.saturating_sub(tsig_size));
+                    if let Some(ref mut signer) = signer {
+                        signer.sign(&mut response_writer, tsig_error);
+                    }
 
                     let len = response_writer.position();
                     let data = response_writer.get_range(0, len);
@@ -115,7 +141,7 @@ impl DNSServer for UDPServer {
                     // Get packets from UDP socket
                     let mut packet_parser = PacketParser::new();
                     let _socket_copy = socket.try_clone().expect("Socket cloning error");
-                    let (_, src) = match socket.recv_from(&mut packet_parser.buffer) {
+                    let (size, src) = match socket.recv_from(&mut packet_parser.buffer) {
                         Ok(x) => x,
                         Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
                             // Timeout occurred or no data available yet, continue to the next iteration of the loop
@@ -127,8 +153,26 @@ impl DNSServer for UDPServer {
                         }
                     };
 
+                    packet_parser.length = size;
+
                     // Parse the received request
-                    let request = DNSPacket::get_dns_packet(&mut packet_parser);
+                    // Malformed requests are answered with FORMERR right away
+                    // The TSIG is checked here, where the raw message is at hand
+                    let request = match DNSPacket::get_dns_packet(&mut packet_parser) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            println!("Malformed request from {}: {}", src, e);
+                            if let Some(mut response) = DNSPacket::format_error(&packet_parser) {
+                                let mut response_writer = PacketWriter::new();
+                                response.write_dns_packet(&mut response_writer);
+                                if let Err(e) = socket.send_to(response_writer.get_range(0, response_writer.position()), src) {
+                                    println!("Error on sending FORMERR response: {:?}", e);
+                                }
+                            }
+                            continue;
+                        }
+                    };
+                    let tsig_status = tsig::verify_request(&packet_parser, &request, &context.tsig_keys);
                     
                     // Print received packet
                     // DNSPacket::print_packet(&request);
@@ -136,7 +180,7 @@ impl DNSServer for UDPServer {
                     // Workers should be notified using the Condvar
                     match self.request_queue.lock() {
                         Ok(mut queue) => {
-                            queue.push_back((src, request)); // Push packet in queue
+                            queue.push_back((src, request, tsig_status)); // Push packet in queue
                             self.request_cond.notify_one(); // Notify one stopped worker
                         }
                         Err(e) => {
@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::acl::{is_allowed, is_key_allowed};
+use crate::name::Name;
+use crate::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+use crate::server_config::ServerContext;
+use crate::zone::{Zone, ZoneConfig};
+use crate::zone_transfer::serial_gt;
+
+// Classes with a special meaning in UPDATE messages (RFC 2136 section 2.4 and 2.5)
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TYPE_ANY: u16 = 255;
+
+// One dynamic update as written to the journal of the zone
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub old_serial: u32,
+    pub new_serial: u32,
+    pub deleted: Vec<DNSRecord>,
+    pub added: Vec<DNSRecord>,
+}
+
+// The journal is next to the master file of the zone
+pub fn journal_path(config: &ZoneConfig) -> String {
+    format!("{}.jnl", config.file)
+}
+
+// Owner, type, class, TTL and RDATA presence of a record of an UPDATE message
+struct RecordInfo {
+    name: Name,
+    qtype: u16,
+    class: u16,
+    ttl: u32,
+    empty: bool,
+}
+
+impl RecordInfo {
+    fn new(record: &DNSRecord) -> RecordInfo {
+        match record {
+            DNSRecord::UNKNOWN { domain, qtype, class, data, ttl } => RecordInfo {
+                name: domain.to_lowercase(),
+                qtype: *qtype,
+                class: *class,
+                ttl: *ttl,
+                empty: data.is_empty(),
+            },
+            _ => RecordInfo {
+                name: record.clone().get_domain().unwrap_or_default().to_lowercase(),
+                qtype: record.clone().get_query_type().to_num(),
+                class: CLASS_IN,
+                ttl: record.clone().get_ttl(),
+                empty: false,
+            },
+        }
+    }
+}
+
+// Meta types like OPT, AXFR or ANY are never stored in a zone (RFC 6895 section 3.1)
+fn is_meta_type(qtype: u16) -> bool {
+    qtype == QueryType::OPT.to_num() || (128..=255).contains(&qtype)
+}
+
+// Answer an UPDATE message (RFC 2136)
+// The prerequisites are checked and the updates applied to the zone in one step
+// key_name is the TSIG key the request was signed with, if any
+pub fn handle_update(request: &DNSPacket, peer: IpAddr, key_name: Option<&Name>, server_context: &ServerContext) -> DNSPacket {
+    let mut response = DNSPacket::new();
+    response.header.id = request.header.id;
+    response.header.query = true;
+    response.header.opcode = request.header.opcode;
+    response.questions = request.questions.clone();
+
+    response.header.rcode = match update_zone(request, peer, key_name, server_context) {
+        Ok(()) => RCode::NOERROR,
+        Err(rcode) => rcode,
+    };
+    response
+}
+
+fn update_zone(request: &DNSPacket, peer: IpAddr, key_name: Option<&Name>, server_context: &ServerContext) -> Result<(), RCode> {
+    // Zone section: exactly one SOA question naming the zone
+    let zone_question = match request.questions.as_slice() {
+        [question] if question.qtype == QueryType::SOA => question,
+        _ => return Err(RCode::FORMERR),
+    };
+    let origin = &zone_question.qname;
+
+    // Only the primary zones loaded from master files can be updated
+    let config = match server_context.zones.iter().find(|config| config.origin == *origin) {
+        Some(x) => x,
+        None => {
+            println!("UPDATE for {} refused: not one of our primary zones", origin);
+            return Err(RCode::NOTAUTH);
+        }
+    };
+    if !is_allowed(peer, &config.allow_update) && !is_key_allowed(key_name, &config.allow_update_keys) {
+        println!("UPDATE for {} refused for {}", origin, peer);
+        return Err(RCode::REFUSED);
+    }
+
+    let prerequisites = &request.answers;
+    let updates = &request.authorities;
+    let result = server_context.authority.update(origin, |zone| {
+        check_prerequisites(zone, prerequisites)?;
+        prescan_updates(zone, updates)?;
+
+        // Changes are made on a copy that replaces the zone only when all went well
+        let mut new_zone = zone.clone();
+        apply_updates(&mut new_zone, updates);
+
+        let entry = match finish_update(zone, &mut new_zone) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        if let Err(e) = write_journal(config, &entry) {
+            println!("Error writing the journal of {}: {}", origin, e);
+            return Err(RCode::SERVFAIL);
+        }
+
+        println!("Zone {} updated to serial {}", origin, entry.new_serial);
+        *zone = new_zone;
+        Ok(())
+    });
+
+    result.unwrap_or(Err(RCode::NOTAUTH))
+}
+
+// Prerequisite section (RFC 2136 section 3.2)
+fn check_prerequisites(zone: &Zone, prerequisites: &[DNSRecord]) -> Result<(), RCode> {
+    // Value dependent prerequisites are compared as whole RRsets
+    let mut rrsets: BTreeMap<(Name, u16), Vec<DNSRecord>> = BTreeMap::new();
+
+    for record in prerequisites {
+        let info = RecordInfo::new(record);
+        if info.ttl != 0 {
+            return Err(RCode::FORMERR);
+        }
+        if !info.name.is_subdomain_of(&zone.origin) {
+            return Err(RCode::NOTZONE);
+        }
+
+        let qtype = QueryType::get_query_type(info.qtype);
+        match info.class {
+            CLASS_ANY if !info.empty => return Err(RCode::FORMERR),
+            // Name is in use
+            CLASS_ANY if info.qtype == TYPE_ANY => {
+                if !zone.records.contains_key(&info.name) {
+                    return Err(RCode::NXDOMAIN);
+                }
+            }
+            // RRset exists (value independent)
+            CLASS_ANY => {
+                if zone.get_records(&info.name, qtype).is_empty() {
+                    return Err(RCode::NXRRSET);
+                }
+            }
+            CLASS_NONE if !info.empty => return Err(RCode::FORMERR),
+            // Name is not in use
+            CLASS_NONE if info.qtype == TYPE_ANY => {
+                if zone.records.contains_key(&info.name) {
+                    return Err(RCode::YXDOMAIN);
+                }
+            }
+            // RRset does not exist
+            CLASS_NONE => {
+                if !zone.get_records(&info.name, qtype).is_empty() {
+                    return Err(RCode::YXRRSET);
+                }
+            }
+            // RRset exists (value dependent)
+            CLASS_IN => rrsets.entry((info.name, info.qtype)).or_default().push(record.clone()),
+            _ => return Err(RCode::FORMERR),
+        }
+    }
+
+    for ((name, qtype), expected) in rrsets {
+        let actual = zone.get_records(&name, QueryType::get_query_type(qtype));
+        let same = actual.len() == expected.len()
+            && expected.iter().all(|record| actual.iter().any(|existing| existing.same_data(record)));
+        if !same {
+            return Err(RCode::NXRRSET);
+        }
+    }
+
+    Ok(())
+}
+
+// Update section checks done before anything is changed (RFC 2136 section 3.4.1)
+fn prescan_updates(zone: &Zone, updates: &[DNSRecord]) -> Result<(), RCode> {
+    for record in updates {
+        let info = RecordInfo::new(record);
+        if !info.name.is_subdomain_of(&zone.origin) {
+            return Err(RCode::NOTZONE);
+        }
+
+        let valid = match info.class {
+            CLASS_IN => !is_meta_type(info.qtype) && !info.empty,
+            CLASS_ANY => info.ttl == 0 && info.empty && (info.qtype == TYPE_ANY || !is_meta_type(info.qtype)),
+            CLASS_NONE => info.ttl == 0 && !is_meta_type(info.qtype),
+            _ => false,
+        };
+        if !valid {
+            return Err(RCode::FORMERR);
+        }
+    }
+
+    Ok(())
+}
+
+// Update section (RFC 2136 section 3.4.2)
+// The SOA and the NS records at the origin are never removed
+fn apply_updates(zone: &mut Zone, updates: &[DNSRecord]) {
+    for record in updates {
+        let info = RecordInfo::new(record);
+        let qtype = QueryType::get_query_type(info.qtype);
+        let at_origin = info.name == zone.origin;
+
+        match info.class {
+            // Add to an RRset
+            CLASS_IN => add_record(zone, record.clone()),
+            // Delete all RRsets from a name
+            CLASS_ANY if info.qtype == TYPE_ANY => {
+                if at_origin {
+                    let records = zone.records.get(&info.name).cloned().unwrap_or_default();
+                    for existing in records {
+                        let existing_type = existing.clone().get_query_type();
+                        if existing_type != QueryType::SOA && existing_type != QueryType::NS {
+                            zone.remove_record(&existing);
+                        }
+                    }
+                } else {
+                    zone.records.remove(&info.name);
+                }
+            }
+            // Delete an RRset
+            CLASS_ANY if !(at_origin && (qtype == QueryType::SOA || qtype == QueryType::NS)) => {
+                zone.remove_rrset(&info.name, qtype);
+            }
+            // Delete an RR from an RRset
+            CLASS_NONE => {
+                if at_origin && qtype == QueryType::SOA {
+                    continue;
+                }
+                if at_origin && qtype == QueryType::NS && zone.get_records(&info.name, qtype).len() <= 1 {
+                    continue;
+                }
+                if let DNSRecord::UNKNOWN { domain, data, .. } = record {
+                    if let Ok(record) = DNSRecord::from_rdata(domain, info.qtype, 0, data) {
+                        zone.remove_record(&record);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn add_record(zone: &mut Zone, record: DNSRecord) {
+    let name = record.clone().get_domain().unwrap_or_default();
+    let qtype = record.clone().get_query_type();
+
+    // The SOA is replaced, and only by a newer one
+    if let DNSRecord::SOA { serial, .. } = record {
+        if name == zone.origin && zone.get_serial().is_some_and(|current| serial_gt(serial, current)) {
+            zone.remove_rrset(&name, QueryType::SOA);
+            zone.add_record(record);
+        }
+        return;
+    }
+
+    // A CNAME cannot coexist with other data at the same name
+    let has_cname = !zone.get_records(&name, QueryType::CNAME).is_empty();
+    let has_other = zone.records.get(&name)
+        .is_some_and(|records| records.iter().any(|existing| existing.clone().get_query_type() != QueryType::CNAME));
+    match qtype {
+        QueryType::CNAME if has_other => return,
+        QueryType::CNAME => zone.remove_rrset(&name, QueryType::CNAME),
+        _ if has_cname => return,
+        _ => {}
+    }
+
+    // A record with the same data only gets the new TTL
+    zone.remove_record(&record);
+    zone.add_record(record);
+}
+
+// Compare the zone before and after the update and bump the serial if it changed
+// Returns None if the update did not change anything
+fn finish_update(old_zone: &Zone, new_zone: &mut Zone) -> Option<JournalEntry> {
+    if zone_diff(old_zone, new_zone) == (Vec::new(), Vec::new()) {
+        return None;
+    }
+
+    let old_serial = old_zone.get_serial().unwrap_or_default();
+    let new_serial = match new_zone.get_serial() {
+        Some(serial) if serial_gt(serial, old_serial) => serial,
+        _ => old_serial.wrapping_add(1),
+    };
+    new_zone.set_serial(new_serial);
+
+    let (deleted, added) = zone_diff(old_zone, new_zone);
+    Some(JournalEntry {
+        old_serial,
+        new_serial,
+        deleted,
+        added,
+    })
+}
+
+// Records only in the old zone and records only in the new zone
+fn zone_diff(old_zone: &Zone, new_zone: &Zone) -> (Vec<DNSRecord>, Vec<DNSRecord>) {
+    let old_records: HashSet<&DNSRecord> = old_zone.records.values().flatten().collect();
+    let new_records: HashSet<&DNSRecord> = new_zone.records.values().flatten().collect();
+
+    // Records in zone order, so the journal does not depend on the hashing
+    let deleted = old_zone.records.values().flatten()
+        .filter(|record| !new_records.contains(record))
+        .cloned()
+        .collect();
+    let added = new_zone.records.values().flatten()
+        .filter(|record| !old_records.contains(record))
+        .cloned()
+        .collect();
+    (deleted, added)
+}
+
+// Append an update to the journal, one JSON entry per line
+fn write_journal(config: &ZoneConfig, entry: &JournalEntry) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(journal_path(config))?;
+    let line = serde_json::to_string(entry)?;
+    writeln!(file, "{}", line)
+}
+
+// Apply the journaled updates that follow the serial of the zone
+// Entries older than the master file are skipped
+pub fn replay_journal(zone: &mut Zone, config: &ZoneConfig) {
+    let data = match fs::read_to_string(journal_path(config)) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+
+    for (number, line) in data.lines().enumerate() {
+        let entry = match serde_json::from_str::<JournalEntry>(line) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Invalid journal entry {} for zone {}: {}", number + 1, zone.origin, e);
+                break;
+            }
+        };
+        if zone.get_serial() != Some(entry.old_serial) {
+            continue;
+        }
+
+        for record in &entry.deleted {
+            zone.remove_record(record);
+        }
+        for record in entry.added {
+            zone.add_record(record);
+        }
+    }
+}
@@ -1,6 +1,18 @@
+use std::collections::HashMap;
+
+use crate::name::{Name, MAX_LABEL_LENGTH};
+
+// Compression pointers hold a 14 bit offset
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
 pub struct PacketWriter {
     pub buffer: [u8; 63000],
     pub position: usize,
+    // Names written with write_name point to earlier copies of their suffixes (RFC 1035 section 4.1.4)
+    // Off by default: RDATA and canonical forms (RFC 4034 section 6.2) need the full names
+    pub compression: bool,
+    // Offsets of the names and suffixes written so far, by their labels
+    names: HashMap<Vec<Vec<u8>>, usize>,
 }
 
 impl PacketWriter {
@@ -9,6 +21,8 @@ impl PacketWriter {
         PacketWriter {
             buffer: [0; 63000],
             position: 0,
+            compression: false,
+            names: HashMap::new(),
         }
     }
 
@@ -39,14 +53,65 @@ impl PacketWriter {
 
     }
 
-    pub fn write_qname(&mut self, qname: &str){
-        for label in qname.split('.') {
-            let length = label.len();
+    // write raw bytes
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write(*b);
+        }
+    }
+
+    // write <length><bytes> character-strings
+    // Data longer than 255 bytes is split over several strings
+    pub fn write_character_string(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            self.write_u8(0);
+            return;
+        }
+
+        for chunk in data.chunks(255) {
+            self.write_u8(chunk.len() as u8);
+            self.write_bytes(chunk);
+        }
+    }
+
+    // A label over 63 bytes would be read back as a pointer: the name is ended before it
+    pub fn write_qname(&mut self, qname: &Name){
+        for label in qname.labels() {
+            if !is_writable(label, qname) {
+                break;
+            }
+            self.write_u8(label.len() as u8);
+            self.write_bytes(label);
+        }
+
+        self.write_u8(0);
+    }
+
+    // Write a name that may be compressed: owner names and the names in
+    // the RDATA of the types defined in RFC 1035 (RFC 3597 section 4)
+    // Suffixes are matched with their case, so the names keep it
+    pub fn write_name(&mut self, name: &Name) {
+        if !self.compression {
+            self.write_qname(name);
+            return;
+        }
 
-            self.write_u8(length as u8);
-            for b in label.as_bytes() {
-                self.write_u8(*b);
+        let labels = name.labels();
+        for i in 0..labels.len() {
+            if !is_writable(&labels[i], name) {
+                break;
             }
+            let suffix = labels[i..].to_vec();
+            if let Some(&offset) = self.names.get(&suffix) {
+                self.write_u16(0xC000 | offset as u16);
+                return;
+            }
+            if self.position <= MAX_POINTER_OFFSET {
+                self.names.insert(suffix, self.position);
+            }
+
+            self.write_u8(labels[i].len() as u8);
+            self.write_bytes(&labels[i]);
         }
 
         self.write_u8(0);
@@ -104,3 +169,12 @@ impl PacketWriter {
     }
 
 }
+
+// Check the length of a label before writing it
+fn is_writable(label: &[u8], name: &Name) -> bool {
+    if label.len() > MAX_LABEL_LENGTH {
+        println!("Refusing to write a label of {} bytes in {}", label.len(), name);
+        return false;
+    }
+    true
+}
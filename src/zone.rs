@@ -0,0 +1,486 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::name::Name;
+use crate::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+use crate::signer::{SigningConfig, ZoneSigner};
+use crate::update::replay_journal;
+use crate::zone_file::{ZoneFileError, ZoneFileParser};
+
+// Longest CNAME chain followed inside our own zones
+const MAX_CNAME_CHAIN: usize = 8;
+
+// A zone listed in the config: its origin and master file
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ZoneConfig {
+    pub origin: Name,
+    pub file: String,
+    // Addresses and CIDR ranges allowed to send dynamic updates (RFC 2136)
+    #[serde(default)]
+    pub allow_update: Vec<String>,
+    // TSIG keys allowed to send dynamic updates
+    #[serde(default)]
+    pub allow_update_keys: Vec<Name>,
+    // Sign the answers with DNSSEC as they are sent
+    #[serde(default)]
+    pub signing: Option<SigningConfig>,
+}
+
+// Data of one authoritative zone
+#[derive(Clone, Debug, Default)]
+pub struct Zone {
+    // Lowercase, like the owner names below
+    pub origin: Name,
+    // Records by lowercase owner name, in canonical order
+    pub records: BTreeMap<Name, Vec<DNSRecord>>,
+    // A secondary zone not refreshed from its primary for longer than its SOA expire
+    pub expired: bool,
+}
+
+impl Zone {
+    pub fn new(origin: &Name) -> Zone {
+        Zone {
+            origin: origin.to_lowercase(),
+            records: BTreeMap::new(),
+            expired: false,
+        }
+    }
+
+    // Build a zone from a list of records, like the ones of a zone transfer
+    pub fn from_records(origin: &Name, records: Vec<DNSRecord>) -> Zone {
+        let mut zone = Zone::new(origin);
+        for record in records {
+            zone.add_record(record);
+        }
+        zone
+    }
+
+    // Load a zone from its master file
+    // The zone must have a SOA record at its origin
+    pub fn load(config: &ZoneConfig) -> Result<Zone, ZoneFileError> {
+        let records = ZoneFileParser::parse_file(Path::new(&config.file), &config.origin)?;
+
+        let mut zone = Zone::from_records(&config.origin, records);
+        if zone.get_soa().is_none() {
+            return Err(ZoneFileError {
+                file: config.file.clone(),
+                line: 0,
+                message: format!("No SOA record for zone {}", config.origin),
+            });
+        }
+
+        // Dynamic updates made since the master file was written
+        replay_journal(&mut zone, config);
+
+        Ok(zone)
+    }
+
+    // Add a record; records outside the zone are ignored
+    pub fn add_record(&mut self, record: DNSRecord) {
+        let domain = match record.clone().get_domain() {
+            Some(x) => x,
+            None => return,
+        };
+        if !domain.is_subdomain_of(&self.origin) {
+            println!("Ignoring out of zone record {:?} in zone {}", record, self.origin);
+            return;
+        }
+
+        let records = self.records.entry(domain.to_lowercase()).or_default();
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+
+    // Remove a record, whatever its TTL; returns false if it was not in the zone
+    pub fn remove_record(&mut self, record: &DNSRecord) -> bool {
+        let name = match record.clone().get_domain() {
+            Some(x) => x,
+            None => return false,
+        };
+        let records = match self.records.get_mut(&name) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        let count = records.len();
+        records.retain(|existing| !existing.same_data(record));
+        let removed = records.len() != count;
+        if records.is_empty() {
+            self.records.remove(&name);
+        }
+        removed
+    }
+
+    // Remove all records of a type owned by name
+    pub fn remove_rrset(&mut self, name: &Name, qtype: QueryType) {
+        if let Some(records) = self.records.get_mut(name) {
+            records.retain(|record| record.clone().get_query_type() != qtype);
+            if records.is_empty() {
+                self.records.remove(name);
+            }
+        }
+    }
+
+    pub fn set_serial(&mut self, new_serial: u32) {
+        if let Some(records) = self.records.get_mut(&self.origin) {
+            for record in records.iter_mut() {
+                if let DNSRecord::SOA { serial, .. } = record {
+                    *serial = new_serial;
+                }
+            }
+        }
+    }
+
+    pub fn get_serial(&self) -> Option<u32> {
+        match self.get_soa()? {
+            DNSRecord::SOA { serial, .. } => Some(*serial),
+            _ => None,
+        }
+    }
+
+    pub fn get_soa(&self) -> Option<&DNSRecord> {
+        self.records.get(&self.origin)?.iter()
+            .find(|record| matches!(record, DNSRecord::SOA { .. }))
+    }
+
+    // Get the records of a type owned by name
+    pub fn get_records(&self, name: &Name, qtype: QueryType) -> Vec<DNSRecord> {
+        match self.records.get(name) {
+            Some(records) => records.iter()
+                .filter(|record| (*record).clone().get_query_type() == qtype)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // A name exists if it owns records or if names below it do (empty non-terminal)
+    pub fn name_exists(&self, name: &Name) -> bool {
+        if self.records.contains_key(name) {
+            return true;
+        }
+
+        // In canonical order the names below a name come right after it
+        self.records.range(name..).next().is_some_and(|(next, _)| next.is_subdomain_of(name))
+    }
+
+    // The names from just below the origin down to name
+    // www.sub.example.com in example.com => [sub.example.com, www.sub.example.com]
+    fn names_below_origin(&self, name: &Name) -> Vec<Name> {
+        let origin_labels = self.origin.label_count();
+
+        let mut names = Vec::new();
+        let mut current = name.to_lowercase();
+        while current.label_count() > origin_labels {
+            names.push(current.clone());
+            current = current.parent().unwrap_or_default();
+        }
+        names.reverse();
+        names
+    }
+
+    // Find the delegation (zone cut) at or above name, below the origin
+    pub fn find_delegation(&self, name: &Name) -> Option<(Name, Vec<DNSRecord>)> {
+        for cut in self.names_below_origin(name) {
+            let ns_records = self.get_records(&cut, QueryType::NS);
+            if !ns_records.is_empty() {
+                return Some((cut, ns_records));
+            }
+        }
+
+        None
+    }
+
+    // Address records of in-zone hosts, for the additional section
+    fn get_glue(&self, host: &Name) -> Vec<DNSRecord> {
+        let mut glue = self.get_records(host, QueryType::A);
+        glue.extend(self.get_records(host, QueryType::AAAA));
+        glue
+    }
+
+    // SOA for the authority section of negative answers
+    // Its TTL is min(SOA TTL, MINIMUM) (RFC 2308)
+    pub fn get_negative_soa(&self) -> Option<DNSRecord> {
+        match self.get_soa()?.clone() {
+            DNSRecord::SOA { domain, mname, rname, serial, refresh, retry, expire, minimum, ttl } => Some(DNSRecord::SOA {
+                domain,
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl: ttl.min(minimum),
+            }),
+            _ => None,
+        }
+    }
+
+    // Answer a query from the zone data (RFC 1034 section 4.3.2)
+    pub fn lookup(&self, qname: &Name, qtype: QueryType) -> DNSPacket {
+        let mut packet = DNSPacket::new();
+        packet.header.authoritative_answer = true;
+
+        let mut current_name = qname.clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            // Below a zone cut we are not authoritative: refer to the child zone
+            // The DS records at the cut are ours (RFC 4035 section 3.1.4.1)
+            let delegation = self.find_delegation(&current_name)
+                .filter(|(cut, _)| qtype != QueryType::DS || *cut != current_name);
+            if let Some((_, ns_records)) = delegation {
+                // The answer already holds a CNAME chain: return it as it is
+                if !packet.answers.is_empty() {
+                    return packet;
+                }
+
+                packet.header.authoritative_answer = false;
+                for record in &ns_records {
+                    if let DNSRecord::NS { host, .. } = record {
+                        if host.is_subdomain_of(&self.origin) {
+                            packet.resources.extend(self.get_glue(host));
+                        }
+                    }
+                }
+                packet.authorities = ns_records;
+                return packet;
+            }
+
+            // Names that do not exist may still be matched by a wildcard (RFC 4592)
+            let source_name = if self.name_exists(&current_name) {
+                current_name.clone()
+            } else if let Some(wildcard) = self.find_wildcard(&current_name) {
+                wildcard
+            } else {
+                // After a CNAME the RCODE is the one of the last name in the chain (RFC 6604)
+                packet.header.rcode = RCode::NXDOMAIN;
+                packet.authorities.extend(self.get_negative_soa());
+                return packet;
+            };
+
+            let answers = self.get_records_as(&source_name, qtype, &current_name);
+            if !answers.is_empty() {
+                for record in &answers {
+                    self.add_additional(record, &mut packet);
+                }
+                packet.answers.extend(answers);
+                return packet;
+            }
+
+            // Follow a CNAME while the target is in this zone
+            let cnames = self.get_records_as(&source_name, QueryType::CNAME, &current_name);
+            if let Some(DNSRecord::CNAME { host, .. }) = cnames.first().cloned() {
+                packet.answers.extend(cnames);
+                if !host.is_subdomain_of(&self.origin) {
+                    return packet;
+                }
+                current_name = host;
+                continue;
+            }
+
+            // NODATA: the name exists, but not with this type
+            packet.authorities.extend(self.get_negative_soa());
+            return packet;
+        }
+
+        println!("CNAME chain too long for {}", qname);
+        packet
+    }
+
+    // Records of source_name, with owner name as their owner
+    // For a wildcard match the records are synthesized for the query name
+    fn get_records_as(&self, source_name: &Name, qtype: QueryType, owner: &Name) -> Vec<DNSRecord> {
+        let mut records = self.get_records(source_name, qtype);
+        if source_name != owner {
+            for record in records.iter_mut() {
+                record.set_domain(owner);
+            }
+        }
+        records
+    }
+
+    // The wildcard matching a name that does not exist (RFC 4592 section 3.3.1)
+    // Only the wildcard right below the closest encloser applies, so an existing
+    // name (even an empty non-terminal) between the wildcard and the query blocks it
+    pub fn find_wildcard(&self, name: &Name) -> Option<Name> {
+        let closest_encloser = self.names_below_origin(name)
+            .into_iter()
+            .rev()
+            .find(|ancestor| self.name_exists(ancestor))
+            .unwrap_or_else(|| self.origin.clone());
+
+        closest_encloser.child(b"*").ok().filter(|wildcard| self.name_exists(wildcard))
+    }
+
+    // All records of the zone in transfer order (RFC 5936 section 2.2)
+    // The SOA comes first and is repeated at the end
+    pub fn transfer_records(&self) -> Vec<DNSRecord> {
+        let soa = match self.get_soa() {
+            Some(soa) => soa.clone(),
+            None => return Vec::new(),
+        };
+
+        let mut records = vec![soa.clone()];
+        for name_records in self.records.values() {
+            records.extend(name_records.iter()
+                .filter(|record| **record != soa)
+                .cloned());
+        }
+        records.push(soa);
+        records
+    }
+
+    // Additional section processing for NS, MX and SRV targets in the zone
+    fn add_additional(&self, record: &DNSRecord, packet: &mut DNSPacket) {
+        let host = match record {
+            DNSRecord::NS { host, .. } | DNSRecord::MX { host, .. } | DNSRecord::SRV { host, .. } => host,
+            _ => return,
+        };
+        if !host.is_subdomain_of(&self.origin) {
+            return;
+        }
+
+        for glue in self.get_glue(host) {
+            if !packet.resources.contains(&glue) {
+                packet.resources.push(glue);
+            }
+        }
+    }
+}
+
+// All zones we are authoritative for, by origin
+#[derive(Default, Debug)]
+pub struct Authority {
+    pub zones: RwLock<BTreeMap<Name, Zone>>,
+    // Online signers of the signed zones, by origin
+    pub signers: RwLock<BTreeMap<Name, Arc<ZoneSigner>>>,
+}
+
+impl Clone for Authority {
+    fn clone(&self) -> Self {
+        let zones_clone = self.zones.read().unwrap().clone();
+        let signers_clone = self.signers.read().unwrap().clone();
+        Authority {
+            zones: RwLock::new(zones_clone),
+            signers: RwLock::new(signers_clone),
+        }
+    }
+}
+
+impl Authority {
+    pub fn new() -> Authority {
+        Authority {
+            zones: RwLock::new(BTreeMap::new()),
+            signers: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    // Load all configured zones; zones failing to load are skipped
+    pub fn load(&self, configs: &[ZoneConfig]) {
+        let mut zones = self.zones.write().unwrap();
+
+        for config in configs {
+            match Zone::load(config) {
+                Ok(mut zone) => {
+                    println!("Loaded zone {} from {}", zone.origin, config.file);
+                    if let Some(signing) = &config.signing {
+                        self.load_signer(&mut zone, signing);
+                    }
+                    zones.insert(zone.origin.clone(), zone);
+                }
+                Err(e) => println!("Failed to load zone {}: {}", config.origin, e),
+            }
+        }
+    }
+
+    // A zone whose keys cannot be loaded is served unsigned
+    fn load_signer(&self, zone: &mut Zone, config: &SigningConfig) {
+        match ZoneSigner::new(&zone.origin, config) {
+            Ok(signer) => {
+                signer.publish(zone);
+                for ds in signer.ds_records() {
+                    println!("DS record for the parent of {}: {}", zone.origin, ds);
+                }
+                if let Ok(mut signers) = self.signers.write() {
+                    signers.insert(zone.origin.clone(), Arc::new(signer));
+                }
+            }
+            Err(e) => println!("Zone {} is not signed: {}", zone.origin, e),
+        }
+    }
+
+    // Origin of the closest zone containing qname
+    pub fn find_zone(&self, qname: &Name) -> Option<Name> {
+        let zones = self.zones.read().ok()?;
+
+        zones.keys()
+            .filter(|origin| qname.is_subdomain_of(origin))
+            .max_by_key(|origin| origin.label_count())
+            .cloned()
+    }
+
+    // Answer from our zones, or None if we are not authoritative for qname
+    // Expired secondary zones are answered with SERVFAIL
+    // Answers of signed zones get their DNSSEC records when dnssec_ok is set
+    pub fn query(&self, qname: &Name, qtype: QueryType, dnssec_ok: bool) -> Option<DNSPacket> {
+        let origin = self.find_zone(qname)?;
+        let zones = self.zones.read().ok()?;
+        let zone = zones.get(&origin)?;
+
+        if zone.expired {
+            let mut packet = DNSPacket::new();
+            packet.header.rcode = RCode::SERVFAIL;
+            return Some(packet);
+        }
+        let mut packet = zone.lookup(qname, qtype);
+        if dnssec_ok {
+            let signer = self.signers.read().ok()?.get(&origin).cloned();
+            if let Some(signer) = signer {
+                signer.sign_answer(zone, qname, &mut packet);
+            }
+        }
+        Some(packet)
+    }
+
+    // Records for a zone transfer, or None if zone is not one of our zones
+    pub fn transfer(&self, zone: &Name) -> Option<Vec<DNSRecord>> {
+        let zones = self.zones.read().ok()?;
+
+        zones.get(zone)
+            .filter(|zone| !zone.expired)
+            .map(|zone| zone.transfer_records())
+    }
+
+    // Copy of one of our zones
+    pub fn get_zone(&self, origin: &Name) -> Option<Zone> {
+        let zones = self.zones.read().ok()?;
+
+        zones.get(origin).cloned()
+    }
+
+    // Add a zone, replacing the one with the same origin
+    pub fn insert(&self, zone: Zone) {
+        if let Ok(mut zones) = self.zones.write() {
+            zones.insert(zone.origin.clone(), zone);
+        }
+    }
+
+    // Change a zone while holding the lock, so concurrent changes are applied one after the other
+    pub fn update<R>(&self, origin: &Name, change: impl FnOnce(&mut Zone) -> R) -> Option<R> {
+        let mut zones = self.zones.write().ok()?;
+
+        zones.get_mut(origin).map(change)
+    }
+
+    pub fn set_expired(&self, origin: &Name) {
+        if let Ok(mut zones) = self.zones.write() {
+            if let Some(zone) = zones.get_mut(origin) {
+                zone.expired = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use std::fmt;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use crate::name::Name;
+use crate::packet::{DNSRecord, QueryType};
+
+// Error while reading a master file, with the place it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneFileError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ZoneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for ZoneFileError {}
+
+// One token of a master file
+// Quoted tokens keep their escapes; the quotes are removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+// One logical entry: a directive or a resource record
+// Parentheses join several physical lines into one entry
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    starts_with_blank: bool,
+    tokens: Vec<Token>,
+}
+
+// Reader for RFC 1035 master files
+// Handles $ORIGIN, $TTL, $INCLUDE, @, relative names, parentheses and comments
+pub struct ZoneFileParser {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+    pub records: Vec<DNSRecord>,
+}
+
+impl ZoneFileParser {
+    pub fn new(origin: &Name) -> ZoneFileParser {
+        ZoneFileParser {
+            origin: origin.to_string(),
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            records: Vec::new(),
+        }
+    }
+
+    // Parse a master file and return all its records
+    pub fn parse_file(path: &Path, origin: &Name) -> Result<Vec<DNSRecord>, ZoneFileError> {
+        let mut parser = ZoneFileParser::new(origin);
+        parser.read_file(path)?;
+
+        Ok(parser.records)
+    }
+
+    // Parse master file text, as if it was read from file_name
+    pub fn parse_str(data: &str, origin: &Name, file_name: &str) -> Result<Vec<DNSRecord>, ZoneFileError> {
+        let mut parser = ZoneFileParser::new(origin);
+        parser.read_data(data, file_name, Path::new("."))?;
+
+        Ok(parser.records)
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<(), ZoneFileError> {
+        let file_name = path.display().to_string();
+        let data = fs::read_to_string(path).map_err(|e| ZoneFileError {
+            file: file_name.clone(),
+            line: 0,
+            message: format!("Unable to read file: {}", e),
+        })?;
+
+        // $INCLUDE paths are relative to the including file
+        let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+        self.read_data(&data, &file_name, &base_dir)
+    }
+
+    fn read_data(&mut self, data: &str, file_name: &str, base_dir: &Path) -> Result<(), ZoneFileError> {
+        let entries = tokenize(data).map_err(|(line, message)| ZoneFileError {
+            file: file_name.to_string(),
+            line,
+            message,
+        })?;
+
+        for entry in entries {
+            let to_error = |message: String| ZoneFileError {
+                file: file_name.to_string(),
+                line: entry.line,
+                message,
+            };
+
+            if !entry.starts_with_blank && entry.tokens[0].text.starts_with('$') {
+                self.read_directive(&entry, base_dir, file_name)?;
+            } else {
+                let record = self.read_record(&entry).map_err(to_error)?;
+                self.records.push(record);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_directive(&mut self, entry: &Entry, base_dir: &Path, file_name: &str) -> Result<(), ZoneFileError> {
+        let to_error = |message: String| ZoneFileError {
+            file: file_name.to_string(),
+            line: entry.line,
+            message,
+        };
+        let argument = |index: usize| {
+            entry.tokens.get(index).map(|token| token.text.as_str())
+                .ok_or_else(|| to_error(format!("Missing argument for {}", entry.tokens[0].text)))
+        };
+
+        match entry.tokens[0].text.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => {
+                self.origin = self.absolute_name(argument(1)?);
+            }
+            "$TTL" => {
+                let ttl = parse_ttl(argument(1)?).ok_or_else(|| to_error("Invalid $TTL".to_string()))?;
+                self.default_ttl = Some(ttl);
+            }
+            "$INCLUDE" => {
+                let include_path = base_dir.join(argument(1)?);
+
+                // The included file may have its own origin;
+                // the origin and owner of the including file are restored after it
+                let saved_origin = self.origin.clone();
+                let saved_owner = self.last_owner.clone();
+                if let Some(origin) = entry.tokens.get(2) {
+                    self.origin = self.absolute_name(&origin.text);
+                }
+                self.read_file(&include_path)?;
+                self.origin = saved_origin;
+                self.last_owner = saved_owner;
+            }
+            other => return Err(to_error(format!("Unknown directive {}", other))),
+        }
+
+        Ok(())
+    }
+
+    fn read_record(&mut self, entry: &Entry) -> Result<DNSRecord, String> {
+        let mut tokens = entry.tokens.iter().peekable();
+
+        // Owner: a line starting with a blank reuses the previous owner
+        let owner = if entry.starts_with_blank {
+            self.last_owner.clone().ok_or("No previous owner name")?
+        } else {
+            let token = tokens.next().ok_or("Missing owner name")?;
+            self.absolute_name(&token.text)
+        };
+        self.last_owner = Some(owner.clone());
+
+        // TTL and class, in any order, before the type
+        let mut ttl = None;
+        loop {
+            let token = *tokens.peek().ok_or("Missing record type")?;
+            if token.quoted {
+                break;
+            } else if let Some(value) = parse_ttl(&token.text) {
+                ttl = Some(value);
+                tokens.next();
+            } else if token.text.eq_ignore_ascii_case("IN") {
+                tokens.next();
+            } else if ["CH", "HS", "CS"].iter().any(|class| token.text.eq_ignore_ascii_case(class)) {
+                return Err(format!("Unsupported class {}", token.text));
+            } else {
+                break;
+            }
+        }
+
+        let type_token = tokens.next().ok_or("Missing record type")?;
+        let qtype = parse_type(&type_token.text).ok_or(format!("Unknown record type {}", type_token.text))?;
+        let rdata_tokens: Vec<&Token> = tokens.collect();
+        let rdata: Vec<&str> = rdata_tokens.iter().map(|token| token.text.as_str()).collect();
+
+        // Explicit TTL, else $TTL, else the last TTL used (RFC 1035)
+        // SOA records may also fall back to their MINIMUM field (RFC 2308)
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(x) => x,
+            None if qtype == QueryType::SOA => rdata.get(6).and_then(|x| parse_ttl(x)).ok_or("Missing TTL")?,
+            None => return Err("Missing TTL".to_string()),
+        };
+        self.last_ttl = Some(ttl);
+
+        self.build_record(owner, qtype, ttl, &rdata, &rdata_tokens)
+    }
+
+    fn build_record(&self, domain: String, qtype: QueryType, ttl: u32, rdata: &[&str], rdata_tokens: &[&Token]) -> Result<DNSRecord, String> {
+        let field = |index: usize| rdata.get(index).copied().ok_or(format!("Missing RDATA for {:?}", qtype));
+        let number = |index: usize| -> Result<u16, String> {
+            field(index)?.parse::<u16>().map_err(|_| format!("Invalid number in {:?} record", qtype))
+        };
+        let time = |index: usize| -> Result<u32, String> {
+            parse_ttl(field(index)?).ok_or(format!("Invalid time value in {:?} record", qtype))
+        };
+        // Names are checked against the label and name length limits
+        let name = |text: &str| -> Result<Name, String> {
+            text.parse::<Name>().map_err(|e| format!("Invalid name {}: {}", text, e))
+        };
+        let domain = name(&domain)?;
+
+        // RFC 3597 generic RDATA: \# <length> <hex>
+        if rdata.first() == Some(&"\\#") {
+            let data = parse_generic_rdata(rdata)?;
+            return Ok(DNSRecord::UNKNOWN {
+                domain,
+                qtype: qtype.to_num(),
+                class: 1,
+                data,
+                ttl,
+            });
+        }
+
+        let record = match qtype {
+            QueryType::A => DNSRecord::A {
+                domain,
+                addr: field(0)?.parse::<Ipv4Addr>().map_err(|e| e.to_string())?,
+                ttl,
+            },
+            QueryType::AAAA => DNSRecord::AAAA {
+                domain,
+                addr: field(0)?.parse::<Ipv6Addr>().map_err(|e| e.to_string())?,
+                ttl,
+            },
+            QueryType::NS => DNSRecord::NS {
+                domain,
+                host: name(&self.absolute_name(field(0)?))?,
+                ttl,
+            },
+            QueryType::CNAME => DNSRecord::CNAME {
+                domain,
+                host: name(&self.absolute_name(field(0)?))?,
+                ttl,
+            },
+            QueryType::PTR => DNSRecord::PTR {
+                domain,
+                host: name(&self.absolute_name(field(0)?))?,
+                ttl,
+            },
+            QueryType::MX => DNSRecord::MX {
+                domain,
+                priority: number(0)?,
+                host: name(&self.absolute_name(field(1)?))?,
+                ttl,
+            },
+            QueryType::SOA => DNSRecord::SOA {
+                domain,
+                mname: name(&self.absolute_name(field(0)?))?,
+                rname: name(&self.absolute_name(field(1)?))?,
+                serial: field(2)?.parse::<u32>().map_err(|_| "Invalid SOA serial".to_string())?,
+                refresh: time(3)?,
+                retry: time(4)?,
+                expire: time(5)?,
+                minimum: time(6)?,
+                ttl,
+            },
+            QueryType::SRV => DNSRecord::SRV {
+                domain,
+                priority: number(0)?,
+                weight: number(1)?,
+                port: number(2)?,
+                host: name(&self.absolute_name(field(3)?))?,
+                ttl,
+            },
+            QueryType::TXT => {
+                if rdata_tokens.is_empty() {
+                    return Err("Missing RDATA for TXT".to_string());
+                }
+                DNSRecord::TXT {
+                    domain,
+                    data: rdata_tokens.iter().map(|token| unescape_text(&token.text)).collect::<Result<_, _>>()?,
+                    ttl,
+                }
+            }
+            _ => return Err(format!("Record type {:?} needs the \\# generic format", qtype)),
+        };
+
+        Ok(record)
+    }
+
+    // Turn a name of the master file into an absolute name without trailing dot
+    fn absolute_name(&self, name: &str) -> String {
+        if name == "@" {
+            self.origin.clone()
+        } else if name == "." {
+            "".to_string()
+        } else if let Some(name) = name.strip_suffix('.').filter(|rest| !ends_with_escape(rest)) {
+            name.to_string()
+        } else if self.origin.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", name, self.origin)
+        }
+    }
+}
+
+// True if text ends with an odd number of backslashes, which escape the character after it
+fn ends_with_escape(text: &str) -> bool {
+    text.bytes().rev().take_while(|byte| *byte == b'\\').count() % 2 == 1
+}
+
+// Split the file into entries, removing comments and joining parentheses
+fn tokenize(data: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (index, line) in data.lines().enumerate() {
+        let line_number = index + 1;
+
+        if depth == 0 {
+            current = Some(Entry {
+                line: line_number,
+                starts_with_blank: line.starts_with(' ') || line.starts_with('\t'),
+                tokens: Vec::new(),
+            });
+        }
+        let entry = current.as_mut().unwrap();
+
+        let mut chars = line.chars().peekable();
+        let mut token = String::new();
+        let mut in_token = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        return Err((line_number, "Unbalanced parentheses".to_string()));
+                    }
+                    depth -= 1;
+                }
+                '"' => {
+                    let mut text = String::new();
+                    let mut closed = false;
+                    while let Some(q) = chars.next() {
+                        match q {
+                            '\\' => {
+                                text.push(q);
+                                if let Some(escaped) = chars.next() {
+                                    text.push(escaped);
+                                }
+                            }
+                            '"' => {
+                                closed = true;
+                                break;
+                            }
+                            _ => text.push(q),
+                        }
+                    }
+                    if !closed {
+                        return Err((line_number, "Unterminated quoted string".to_string()));
+                    }
+                    entry.tokens.push(Token { text, quoted: true });
+                    continue;
+                }
+                '\\' => {
+                    token.push(c);
+                    if let Some(escaped) = chars.next() {
+                        token.push(escaped);
+                    }
+                    in_token = true;
+                    continue;
+                }
+                c if c.is_whitespace() => {}
+                _ => {
+                    token.push(c);
+                    in_token = true;
+                    continue;
+                }
+            }
+
+            // Any separator ends the current token
+            if in_token {
+                entry.tokens.push(Token { text: std::mem::take(&mut token), quoted: false });
+                in_token = false;
+            }
+        }
+        if in_token {
+            entry.tokens.push(Token { text: token, quoted: false });
+        }
+
+        if depth == 0 {
+            let entry = current.take().unwrap();
+            if !entry.tokens.is_empty() {
+                entries.push(entry);
+            }
+        }
+    }
+
+    if depth != 0 {
+        return Err((data.lines().count(), "Unbalanced parentheses".to_string()));
+    }
+
+    Ok(entries)
+}
+
+// Parse a record type mnemonic or the RFC 3597 TYPEnnn form
+pub fn parse_type(text: &str) -> Option<QueryType> {
+    let upper = text.to_ascii_uppercase();
+    let qtype = match upper.as_str() {
+        "A" => QueryType::A,
+        "NS" => QueryType::NS,
+        "CNAME" => QueryType::CNAME,
+        "SOA" => QueryType::SOA,
+        "PTR" => QueryType::PTR,
+        "MX" => QueryType::MX,
+        "TXT" => QueryType::TXT,
+        "AAAA" => QueryType::AAAA,
+        "SRV" => QueryType::SRV,
+        _ => {
+            let num = upper.strip_prefix("TYPE")?.parse::<u16>().ok()?;
+            QueryType::get_query_type(num)
+        }
+    };
+
+    Some(qtype)
+}
+
+// Parse a TTL or time value: plain seconds or units like 1h30m (w, d, h, m, s)
+pub fn parse_ttl(text: &str) -> Option<u32> {
+    if text.is_empty() || !text.as_bytes()[0].is_ascii_digit() {
+        return None;
+    }
+    if let Ok(value) = text.parse::<u32>() {
+        return Some(value);
+    }
+
+    let mut total: u32 = 0;
+    let mut value: u32 = 0;
+    let mut has_digits = false;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = value.checked_mul(10)?.checked_add(digit)?;
+            has_digits = true;
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604800,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        if !has_digits {
+            return None;
+        }
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        value = 0;
+        has_digits = false;
+    }
+
+    total.checked_add(value)
+}
+
+// Parse RFC 3597 generic RDATA: \# <length> <hex words>
+fn parse_generic_rdata(rdata: &[&str]) -> Result<Vec<u8>, String> {
+    let length = rdata.get(1).and_then(|x| x.parse::<usize>().ok()).ok_or("Invalid \\# length")?;
+    let hex: String = rdata[2..].concat();
+    if !hex.len().is_multiple_of(2) {
+        return Err("Odd number of hex digits".to_string());
+    }
+
+    let data = (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "Invalid hex in \\# RDATA".to_string())?;
+
+    if data.len() != length {
+        return Err(format!("\\# length {} does not match {} bytes of data", length, data.len()));
+    }
+
+    Ok(data)
+}
+
+// Turn a character-string of the master file into bytes
+// \X is the character X, \DDD is the byte with decimal value DDD
+pub fn unescape_text(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 1 < bytes.len() {
+            let digits = &bytes[i + 1..bytes.len().min(i + 4)];
+            if digits.len() == 3 && digits.iter().all(u8::is_ascii_digit) {
+                let value = (digits[0] - b'0') as u32 * 100 + (digits[1] - b'0') as u32 * 10 + (digits[2] - b'0') as u32;
+                if value > 255 {
+                    return Err(format!("Escape \\{} is not a byte in {:?}", value, text));
+                }
+                result.push(value as u8);
+                i += 4;
+            } else {
+                result.push(bytes[i + 1]);
+                i += 2;
+            }
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,248 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::acl::{is_allowed, is_key_allowed};
+use crate::name::Name;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, QueryType, RCode};
+use crate::parser::PacketParser;
+use crate::server_config::ServerContext;
+use crate::stub_resolver::random_id;
+use crate::tcp_connection::write_tcp_packet;
+use crate::tsig::{TsigContext, TsigKey};
+use crate::writer::PacketWriter;
+use crate::zone::Zone;
+
+// Records are packed in messages of at most this size
+const AXFR_MESSAGE_SIZE: usize = 16384;
+
+// How long to wait for each message of a transfer from a primary
+pub const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Limits of a whole transfer, so that a primary cannot keep us busy forever
+const MAX_TRANSFER_TIME: Duration = Duration::from_secs(600);
+const MAX_TRANSFER_RECORDS: usize = 1_000_000;
+
+// Answer an AXFR query (RFC 5936)
+// Returns the messages to send on the TCP stream, in order
+// key_name is the TSIG key the request was signed with, if any
+pub fn handle_axfr(request: &DNSPacket, peer: IpAddr, key_name: Option<&Name>, server_context: &ServerContext) -> Vec<DNSPacket> {
+    let mut response = DNSPacket::new();
+    response.header.id = request.header.id;
+    response.header.query = true;
+    response.header.opcode = request.header.opcode;
+    response.questions = request.questions.clone();
+
+    let question = match request.questions.first() {
+        Some(x) => x,
+        None => {
+            response.header.rcode = RCode::FORMERR;
+            return vec![response];
+        }
+    };
+
+    if !is_allowed(peer, &server_context.allow_transfer) && !is_key_allowed(key_name, &server_context.allow_transfer_keys) {
+        println!("Zone transfer of {} refused for {}", question.qname, peer);
+        response.header.rcode = RCode::REFUSED;
+        return vec![response];
+    }
+
+    let records = match server_context.authority.transfer(&question.qname) {
+        Some(records) if !records.is_empty() => records,
+        _ => {
+            println!("Zone transfer of {} refused: not one of our zones", question.qname);
+            response.header.rcode = RCode::NOTAUTH;
+            return vec![response];
+        }
+    };
+
+    println!("Zone transfer of {} to {}: {} records", question.qname, peer, records.len());
+    response.header.authoritative_answer = true;
+    split_messages(response, records)
+}
+
+// Spread the records over as many messages as needed
+// Only the first message carries the question
+pub fn split_messages(first: DNSPacket, records: Vec<DNSRecord>) -> Vec<DNSPacket> {
+    let mut next = first.clone();
+    next.questions.clear();
+
+    let mut messages = Vec::new();
+    let mut current = first;
+    let mut size = packet_size(&current);
+
+    let mut measure = PacketWriter::new();
+    for record in records {
+        measure.position = 0;
+        let record_size = record.write_record(&mut measure);
+
+        if !current.answers.is_empty() && size + record_size > AXFR_MESSAGE_SIZE {
+            messages.push(current);
+            current = next.clone();
+            size = packet_size(&current);
+        }
+
+        size += record_size;
+        current.answers.push(record);
+    }
+
+    messages.push(current);
+    messages
+}
+
+fn packet_size(packet: &DNSPacket) -> usize {
+    let mut writer = PacketWriter::new();
+    packet.clone().write_dns_packet(&mut writer);
+    writer.position()
+}
+
+// Serial number comparison (RFC 1982): true if a is newer than b
+pub fn serial_gt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+fn get_serial(record: &DNSRecord) -> Option<u32> {
+    match record {
+        DNSRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    }
+}
+
+// Copy a whole zone from a primary (AXFR)
+// With a key the query is signed and every answer must be signed by the primary
+pub fn request_axfr(origin: &Name, server: (Ipv4Addr, u16), key: Option<&TsigKey>) -> io::Result<Zone> {
+    let records = request_transfer(origin, QueryType::AXFR, None, server, key)?;
+    if records.len() < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid AXFR answer"));
+    }
+
+    Ok(Zone::from_records(origin, records))
+}
+
+// Bring a zone up to date with the changes since its serial (IXFR, RFC 1995)
+// The primary may answer with the whole zone instead of the changes
+pub fn request_ixfr(zone: &Zone, server: (Ipv4Addr, u16), key: Option<&TsigKey>) -> io::Result<Zone> {
+    let soa = zone.get_soa()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Zone has no SOA"))?;
+    let records = request_transfer(&zone.origin, QueryType::IXFR, Some(soa), server, key)?;
+
+    // Only the current SOA: we are up to date
+    if records.len() == 1 {
+        return Ok(zone.clone());
+    }
+
+    // The second record is not the SOA of a change sequence: this is a full zone
+    if get_serial(&records[1]).is_none() || records.len() == 2 {
+        return Ok(Zone::from_records(&zone.origin, records));
+    }
+
+    apply_ixfr(zone, &records)
+}
+
+// Apply the change sequences of an IXFR answer:
+// new SOA, (old SOA, deleted records, new SOA, added records)*, new SOA
+pub fn apply_ixfr(zone: &Zone, records: &[DNSRecord]) -> io::Result<Zone> {
+    if get_serial(&records[1]) != zone.get_serial() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "IXFR does not start at our serial"));
+    }
+
+    let mut zone = zone.clone();
+    let mut deleting = false;
+    for record in &records[1..records.len() - 1] {
+        // Every SOA switches between the deleted and the added records
+        if get_serial(record).is_some() {
+            deleting = !deleting;
+        }
+
+        if deleting {
+            zone.remove_record(record);
+        } else {
+            zone.add_record(record.clone());
+        }
+    }
+
+    if zone.get_serial() != get_serial(&records[0]) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "IXFR does not end at the new serial"));
+    }
+    Ok(zone)
+}
+
+// Send an AXFR or IXFR query over TCP and collect the records of all answer messages
+fn request_transfer(origin: &Name, qtype: QueryType, soa: Option<&DNSRecord>, server: (Ipv4Addr, u16), key: Option<&TsigKey>) -> io::Result<Vec<DNSRecord>> {
+    let deadline = Instant::now() + MAX_TRANSFER_TIME;
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::from(server), TRANSFER_TIMEOUT)?;
+
+    let mut query = DNSPacket::new();
+    query.header.id = random_id();
+    let mut question = DNSQuestion::new();
+    question.qname = origin.clone();
+    question.qtype = qtype;
+    question.class = 1;
+    query.questions.push(question);
+    // IXFR carries the SOA of our version in the authority section
+    query.authorities.extend(soa.cloned());
+    let mut tsig = key.map(|key| TsigContext::new(key.clone()));
+    write_tcp_packet(&mut stream, &mut query, tsig.as_mut())?;
+
+    let known_serial = soa.and_then(get_serial);
+    let mut records = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Transfer takes too long"));
+        }
+        stream.set_read_timeout(Some(remaining.min(TRANSFER_TIMEOUT)))?;
+
+        let mut parser = PacketParser::new();
+        parser.tcp_stream_to_bytes(&mut stream)?;
+        let message = DNSPacket::get_dns_packet(&mut parser)?;
+
+        if message.header.id != query.header.id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Answer does not match the query"));
+        }
+        if message.header.rcode != RCode::NOERROR {
+            return Err(io::Error::other(format!("Transfer failed with {:?}", message.header.rcode)));
+        }
+        if let Some(ref mut tsig) = tsig {
+            if let Err(error) = tsig.verify(&parser, &message) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("TSIG verification of the transfer failed: {}", error)));
+            }
+        }
+
+        records.extend(message.answers);
+        if records.len() > MAX_TRANSFER_RECORDS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Transfer has too many records"));
+        }
+        if records.is_empty() || get_serial(&records[0]).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Transfer does not start with a SOA"));
+        }
+        if transfer_complete(&records, known_serial) {
+            // The last message must be signed (RFC 8945 section 5.3.1)
+            if tsig.as_ref().is_some_and(|tsig| tsig.is_pending()) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Last message of the transfer is not signed"));
+            }
+            return Ok(records);
+        }
+    }
+}
+
+// Check if the records received so far make a whole transfer
+// The serial of the first SOA is seen twice at the end of an AXFR,
+// and three times at the end of an incremental IXFR
+fn transfer_complete(records: &[DNSRecord], known_serial: Option<u32>) -> bool {
+    let serial = match get_serial(&records[0]) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    // IXFR answer for a zone that did not change
+    if records.len() == 1 {
+        return known_serial.is_some_and(|known| !serial_gt(serial, known));
+    }
+
+    let incremental = get_serial(&records[1]).is_some_and(|second| second != serial);
+    let count = records.iter().filter(|record| get_serial(record) == Some(serial)).count();
+    let last_is_soa = get_serial(&records[records.len() - 1]) == Some(serial);
+
+    last_is_soa && count == if incremental { 3 } else { 2 }
+}
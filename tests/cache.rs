@@ -0,0 +1,154 @@
+// Aggressive use of the cached NSEC and NSEC3 records (RFC 8198)
+use rusty_twisted::cache::Cache;
+use rusty_twisted::dnssec::{base32hex, nsec3_hash};
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+
+const A: u16 = 1;
+const NS: u16 = 2;
+const SOA: u16 = 6;
+const MX: u16 = 15;
+const RRSIG: u16 = 46;
+const NSEC: u16 = 47;
+const NSEC3: u16 = 50;
+
+fn name(text: &str) -> Name {
+    Name::try_from(text).unwrap()
+}
+
+fn soa(ttl: u32, minimum: u32) -> DNSRecord {
+    DNSRecord::SOA {
+        domain: name("example.test"),
+        mname: name("ns1.example.test"),
+        rname: name("hostmaster.example.test"),
+        serial: 1,
+        refresh: 3600,
+        retry: 600,
+        expire: 86400,
+        minimum,
+        ttl,
+    }
+}
+
+fn nsec(owner: &str, next: &str, types: &[u16], ttl: u32) -> DNSRecord {
+    DNSRecord::NSEC { domain: name(owner), next: name(next), types: types.to_vec(), ttl }
+}
+
+// The NSEC chain of a zone holding example.test, a.example.test and c.example.test
+fn nsec_chain(ttl: u32) -> Vec<DNSRecord> {
+    vec![
+        nsec("example.test", "a.example.test", &[NS, SOA, RRSIG, NSEC], ttl),
+        nsec("a.example.test", "c.example.test", &[A, RRSIG, NSEC], ttl),
+        nsec("c.example.test", "example.test", &[A, MX, RRSIG, NSEC], ttl),
+    ]
+}
+
+fn authorities(soa: DNSRecord, denials: Vec<DNSRecord>) -> Vec<DNSRecord> {
+    let mut records = vec![soa];
+    records.extend(denials);
+    records
+}
+
+fn ttls(packet: &DNSPacket) -> Vec<u32> {
+    packet.authorities.iter().map(|record| record.clone().get_ttl()).collect()
+}
+
+#[test]
+fn cached_nsec_records_answer_nxdomain_and_nodata() {
+    let mut cache = Cache::new();
+    cache.store_denial(&authorities(soa(3600, 300), nsec_chain(3600)));
+
+    // b.example.test falls between a and c, and the wildcard before a
+    let packet = cache.lookup_denial(&name("b.example.test"), QueryType::A).unwrap();
+    assert_eq!(packet.header.rcode, RCode::NXDOMAIN);
+    assert!(packet.header.authed_data);
+    assert!(matches!(packet.authorities[0], DNSRecord::SOA { .. }));
+
+    // The name exists without the type asked for
+    let packet = cache.lookup_denial(&name("A.example.test"), QueryType::MX).unwrap();
+    assert_eq!(packet.header.rcode, RCode::NOERROR);
+    assert!(packet.answers.is_empty());
+
+    // Existing data and names of other zones are not denied
+    assert!(cache.lookup_denial(&name("c.example.test"), QueryType::MX).is_none());
+    assert!(cache.lookup_denial(&name("b.example.org"), QueryType::A).is_none());
+
+    let stats = cache.denial_stats;
+    assert_eq!((stats.nxdomain, stats.nodata), (1, 1));
+}
+
+#[test]
+fn cached_nsec3_records_answer_nxdomain_and_nodata() {
+    // A chain of two hashes covers every other hash
+    let hash_origin = nsec3_hash(&name("example.test"), &[], 0);
+    let hash_a = nsec3_hash(&name("a.example.test"), &[], 0);
+    let nsec3 = |hash: &[u8], next: &[u8], types: &[u16]| DNSRecord::NSEC3 {
+        domain: name(&format!("{}.example.test", base32hex(hash))),
+        hash_algorithm: 1,
+        flags: 0,
+        iterations: 0,
+        salt: Vec::new(),
+        next_hashed: next.to_vec(),
+        types: types.to_vec(),
+        ttl: 3600,
+    };
+    let chain = vec![
+        nsec3(&hash_origin, &hash_a, &[NS, SOA, RRSIG]),
+        nsec3(&hash_a, &hash_origin, &[A, RRSIG]),
+    ];
+
+    let mut cache = Cache::new();
+    cache.store_denial(&authorities(soa(3600, 300), chain));
+
+    let packet = cache.lookup_denial(&name("b.example.test"), QueryType::A).unwrap();
+    assert_eq!(packet.header.rcode, RCode::NXDOMAIN);
+    assert!(packet.authorities.iter().any(|record| record.clone().get_query_type().to_num() == NSEC3));
+
+    let packet = cache.lookup_denial(&name("a.example.test"), QueryType::MX).unwrap();
+    assert_eq!(packet.header.rcode, RCode::NOERROR);
+    assert!(cache.lookup_denial(&name("a.example.test"), QueryType::A).is_none());
+}
+
+#[test]
+fn denial_ttls_are_capped_by_the_negative_ttl() {
+    // The SOA minimum caps the NSEC TTL of an hour
+    let mut cache = Cache::new();
+    cache.store_denial(&authorities(soa(3600, 300), nsec_chain(3600)));
+    let packet = cache.lookup_denial(&name("b.example.test"), QueryType::A).unwrap();
+    assert!(ttls(&packet).iter().all(|ttl| *ttl > 0 && *ttl <= 300));
+
+    // And so does the SOA TTL; shorter NSEC TTLs are kept
+    let mut cache = Cache::new();
+    cache.store_denial(&authorities(soa(120, 300), nsec_chain(60)));
+    let packet = cache.lookup_denial(&name("b.example.test"), QueryType::A).unwrap();
+    assert!(ttls(&packet).iter().all(|ttl| *ttl > 0 && *ttl <= 60));
+
+    // A negative TTL of 0 leaves nothing to answer from
+    let mut cache = Cache::new();
+    cache.store_denial(&authorities(soa(3600, 0), nsec_chain(3600)));
+    assert!(cache.lookup_denial(&name("b.example.test"), QueryType::A).is_none());
+
+    // Without the SOA the records are not kept at all
+    let mut cache = Cache::new();
+    cache.store_denial(&nsec_chain(3600));
+    assert!(cache.denials.is_empty());
+}
+
+#[test]
+fn each_zone_keeps_at_most_10000_denial_records() {
+    let records: Vec<DNSRecord> = (0..10050)
+        .map(|i| nsec(&format!("n{:05}.example.test", i), &format!("n{:05}.example.test", i + 1), &[A, NSEC], 3600))
+        .collect();
+
+    let mut cache = Cache::new();
+    cache.store_denial(&authorities(soa(3600, 300), records));
+    let zone = name("example.test");
+    assert_eq!(cache.denials[&zone].records.len(), 10000);
+
+    // Records already kept are still refreshed, new ones are dropped
+    cache.store_denial(&authorities(soa(3600, 300), nsec_chain(3600)));
+    assert_eq!(cache.denials[&zone].records.len(), 10000);
+    let key = (name("n00000.example.test"), NSEC);
+    cache.store_denial(&authorities(soa(3600, 300), vec![nsec("n00000.example.test", "n00002.example.test", &[A, NSEC], 3600)]));
+    assert!(matches!(&cache.denials[&zone].records[&key].record, DNSRecord::NSEC { next, .. } if *next == name("n00002.example.test")));
+}
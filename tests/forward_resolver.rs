@@ -0,0 +1,26 @@
+// SRTT bookkeeping of the forwarder upstreams
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use rusty_twisted::forward_resolver::UpstreamStats;
+
+const UPSTREAM: (Ipv4Addr, u16) = (Ipv4Addr::new(192, 0, 2, 53), 53);
+
+#[test]
+fn failures_double_the_penalty_from_the_timeout() {
+    let stats = UpstreamStats::new();
+    let timeout = Duration::from_millis(500);
+
+    // The first failure costs one timeout, the next ones double it up to 4 timeouts
+    for expected in [500.0, 1000.0, 2000.0, 2000.0] {
+        stats.record_failure(UPSTREAM, timeout);
+        assert_eq!(stats.get_srtt(UPSTREAM), expected);
+    }
+
+    // A fast upstream that fails is pushed back to the timeout at least
+    let stats = UpstreamStats::new();
+    stats.record_rtt(UPSTREAM, Duration::from_millis(20));
+    assert_eq!(stats.get_srtt(UPSTREAM), 20.0);
+    stats.record_failure(UPSTREAM, timeout);
+    assert_eq!(stats.get_srtt(UPSTREAM), 500.0);
+}
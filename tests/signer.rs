@@ -0,0 +1,160 @@
+// Answers signed by the online signer verify with the DNSKEYs it publishes
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusty_twisted::dnssec::{base32hex, find_denial, get_rrsets, get_rrsigs, nsec3_hash, signed_data, verify_signature, Dnskey, NSEC3};
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+use rusty_twisted::signer::{DenialType, SigningAlgorithm, SigningConfig, SigningKeyConfig, ZoneSigner};
+use rusty_twisted::zone::Zone;
+use rusty_twisted::zone_file::ZoneFileParser;
+
+const ZONE: &str = "
+$ORIGIN example.test.
+$TTL 300
+@       SOA ns1 hostmaster 1 3600 600 86400 300
+        NS  ns1
+        MX  10 mail
+ns1     A   192.0.2.1
+mail    A   192.0.2.25
+www     A   192.0.2.80
+*.dev   A   192.0.2.100
+";
+
+// A directory of its own for the key files, made again for every run
+fn key_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rusty-twisted-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+// A signed zone with a KSK and a ZSK
+fn signed_zone(dir: &Path, denial: DenialType) -> (Zone, ZoneSigner) {
+    let key = |file: &str, ksk| SigningKeyConfig {
+        file: dir.join(file).to_string_lossy().to_string(),
+        algorithm: SigningAlgorithm::EcdsaP256Sha256,
+        ksk,
+    };
+    let config = SigningConfig {
+        keys: vec![key("zsk.pem", false), key("ksk.pem", true)],
+        denial,
+        signature_validity: 3600,
+        nsec3_iterations: 0,
+        nsec3_salt: "AABBCCDD".to_string(),
+    };
+    let origin = Name::try_from("example.test").unwrap();
+    let records = ZoneFileParser::parse_str(ZONE, &origin, "example.test.zone").unwrap();
+    let mut zone = Zone::from_records(&origin, records);
+    let signer = ZoneSigner::new(&origin, &config).unwrap();
+    signer.publish(&mut zone);
+    (zone, signer)
+}
+
+fn query(zone: &Zone, signer: &ZoneSigner, qname: &str, qtype: QueryType) -> DNSPacket {
+    let qname = Name::try_from(qname).unwrap();
+    let mut packet = zone.lookup(&qname, qtype);
+    signer.sign_answer(zone, &qname, &mut packet);
+    packet
+}
+
+// Check if every RRset of the section has signatures by the zone that verify with its keys
+fn is_signed(zone: &Zone, records: &[DNSRecord]) -> bool {
+    let origin = Name::try_from("example.test").unwrap();
+    let keys: Vec<Dnskey> = zone.get_records(&origin, QueryType::DNSKEY).iter()
+        .filter_map(Dnskey::from_record)
+        .collect();
+    let rrsigs = get_rrsigs(records);
+
+    get_rrsets(records).iter().all(|(owner, rrset_type, rrset)| {
+        let signatures: Vec<_> = rrsigs.iter()
+            .filter(|(signed_owner, rrsig)| signed_owner == owner && rrsig.type_covered == *rrset_type)
+            .collect();
+        !signatures.is_empty() && signatures.iter().all(|(_, rrsig)| {
+            let data = signed_data(rrsig, owner, rrset);
+            rrsig.signer == origin && keys.iter()
+                .filter(|key| key.key_tag() == rrsig.key_tag)
+                .any(|key| verify_signature(key, &data, &rrsig.signature))
+        })
+    })
+}
+
+// Check if one of the NSEC3 records covers the hash of name
+fn nsec3_covers(records: &[DNSRecord], name: &str) -> bool {
+    let hash = base32hex(&nsec3_hash(&Name::try_from(name).unwrap(), &[0xAA, 0xBB, 0xCC, 0xDD], 0));
+    records.iter().any(|record| match record {
+        DNSRecord::NSEC3 { domain, next_hashed, .. } => {
+            let owner = String::from_utf8_lossy(&domain.labels()[0]).to_lowercase();
+            let next = base32hex(next_hashed);
+            if owner < next { owner < hash && hash < next } else { owner < hash || hash < next }
+        }
+        _ => false,
+    })
+}
+
+#[test]
+fn answers_verify_in_every_denial_mode() {
+    for denial in [DenialType::Nsec, DenialType::Nsec3, DenialType::Compact] {
+        let dir = key_dir(&format!("{:?}", denial));
+        let (zone, signer) = signed_zone(&dir, denial);
+
+        for (qname, qtype) in [("www.example.test", QueryType::A), ("example.test", QueryType::MX), ("example.test", QueryType::DNSKEY)] {
+            let packet = query(&zone, &signer, qname, qtype);
+            assert_eq!(packet.header.rcode, RCode::NOERROR);
+            assert!(!packet.answers.is_empty());
+            assert!(is_signed(&zone, &packet.answers), "{} {:?}", qname, qtype);
+        }
+
+        // Wildcard answers carry the label count of the wildcard (RFC 4035 section 5.3.4)
+        // and a proof that the name itself does not exist
+        let packet = query(&zone, &signer, "x.dev.example.test", QueryType::A);
+        assert!(is_signed(&zone, &packet.answers));
+        assert!(!packet.authorities.is_empty());
+        assert!(is_signed(&zone, &packet.authorities));
+        let rrsigs = get_rrsigs(&packet.answers);
+        assert!(rrsigs.iter().all(|(_, rrsig)| rrsig.labels == 3));
+
+        // Names that do not exist and names without the type asked for
+        let packet = query(&zone, &signer, "nope.example.test", QueryType::A);
+        assert!(is_signed(&zone, &packet.authorities));
+        let expected = if denial == DenialType::Compact { RCode::NOERROR } else { RCode::NXDOMAIN };
+        assert_eq!(packet.header.rcode, expected);
+        assert!(find_denial(&packet.authorities, &Name::try_from("nope.example.test").unwrap(), 1).is_some());
+
+        let packet = query(&zone, &signer, "www.example.test", QueryType::MX);
+        assert!(is_signed(&zone, &packet.authorities));
+        let (rcode, _) = find_denial(&packet.authorities, &Name::try_from("www.example.test").unwrap(), 15).unwrap();
+        assert_eq!(rcode, RCode::NOERROR);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn nsec3_denial_covers_the_right_hashes() {
+    let dir = key_dir("nsec3");
+    let (zone, signer) = signed_zone(&dir, DenialType::Nsec3);
+
+    // NXDOMAIN: the next closer name and the wildcard at the closest encloser are covered
+    let packet = query(&zone, &signer, "a.nope.example.test", QueryType::A);
+    assert_eq!(packet.header.rcode, RCode::NXDOMAIN);
+    assert!(is_signed(&zone, &packet.authorities));
+    assert!(nsec3_covers(&packet.authorities, "nope.example.test"));
+    assert!(nsec3_covers(&packet.authorities, "*.example.test"));
+    let (rcode, proof) = find_denial(&packet.authorities, &Name::try_from("a.nope.example.test").unwrap(), 1).unwrap();
+    assert_eq!(rcode, RCode::NXDOMAIN);
+    assert!(proof.iter().any(|record| record.clone().get_query_type().to_num() == NSEC3));
+
+    // The wildcard answer proves that the next closer name does not exist
+    let packet = query(&zone, &signer, "x.dev.example.test", QueryType::A);
+    assert_eq!(packet.answers.len(), 2);
+    assert!(nsec3_covers(&packet.authorities, "x.dev.example.test"));
+
+    // Changed data no longer verifies
+    let mut packet = query(&zone, &signer, "www.example.test", QueryType::A);
+    if let DNSRecord::A { addr, .. } = &mut packet.answers[0] {
+        *addr = "192.0.2.81".parse().unwrap();
+    }
+    assert!(!is_signed(&zone, &packet.answers));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
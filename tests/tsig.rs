@@ -0,0 +1,111 @@
+// Known answers for TSIG with HMAC-SHA256 (RFC 8945)
+// The messages follow RFC 8945 section 4, with the MACs computed apart from this crate
+// for the key test-key and the secret below
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::{DNSPacket, DNSQuestion, DNSRecord, QueryType};
+use rusty_twisted::parser::PacketParser;
+use rusty_twisted::tsig::{TsigContext, TsigKey, BADSIG, BADTIME};
+use rusty_twisted::writer::PacketWriter;
+
+const SECRET: &str = "sO74msvyroT0cpoi4A2NM4IfkUu++3kCE7qIc3RmgNg=";
+const TIME_SIGNED: u64 = 1700000000;
+
+// Query for example.com A with RD, ID 0x3a5c
+const REQUEST: &str = "3a5c01000001000000000001076578616d706c6503636f6d0000010001\
+    08746573742d6b65790000fa00ff00000000003d0b686d61632d7368613235360000006553f100012c0020\
+    79b4e061f3e871e3466cc9dd0edc0fdf0a5f6c712b043c883731b7d352ef48c63a5c00000000";
+
+// Its answer signed a second later, with the MAC of the request first in the MAC data
+const RESPONSE: &str = "3a5c85000001000100000001076578616d706c6503636f6d0000010001\
+    c00c000100010000012c0004c0000201\
+    08746573742d6b65790000fa00ff00000000003d0b686d61632d7368613235360000006553f101012c0020\
+    07d388d77a407f7def90802e9ec2c6b844908763c7d8292e65e34e300226cbaf3a5c00000000";
+
+fn key() -> TsigKey {
+    TsigKey {
+        name: Name::try_from("test-key").unwrap(),
+        algorithm: Name::try_from("hmac-sha256").unwrap(),
+        secret: SECRET.to_string(),
+    }
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    let text: String = text.split_whitespace().collect();
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn written(writer: &PacketWriter) -> Vec<u8> {
+    writer.buffer[..writer.position()].to_vec()
+}
+
+fn parse(data: &[u8]) -> (PacketParser, DNSPacket) {
+    let mut parser = PacketParser::from_bytes(data);
+    let packet = DNSPacket::get_dns_packet(&mut parser).unwrap();
+    (parser, packet)
+}
+
+fn request() -> DNSPacket {
+    let mut packet = DNSPacket::new();
+    packet.header.id = 0x3a5c;
+    packet.header.recursion_desired = true;
+    packet.questions.push(DNSQuestion {
+        qname: Name::try_from("example.com").unwrap(),
+        qtype: QueryType::A,
+        class: 1,
+    });
+    packet
+}
+
+fn response() -> DNSPacket {
+    let mut packet = request();
+    packet.header.query = true;
+    packet.header.authoritative_answer = true;
+    packet.answers.push(DNSRecord::A {
+        domain: Name::try_from("example.com").unwrap(),
+        addr: "192.0.2.1".parse().unwrap(),
+        ttl: 300,
+    });
+    packet
+}
+
+#[test]
+fn signed_exchange_matches_the_known_answers() {
+    let mut client = TsigContext::new(key());
+    let mut writer = PacketWriter::new();
+    request().write_dns_packet(&mut writer);
+    client.sign_at(&mut writer, 0, TIME_SIGNED);
+    assert_eq!(written(&writer), hex(REQUEST));
+
+    let mut server = TsigContext::new(key());
+    let (parser, packet) = parse(&hex(REQUEST));
+    assert_eq!(server.verify_at(&parser, &packet, TIME_SIGNED), Ok(()));
+
+    let mut writer = PacketWriter::new();
+    response().write_dns_packet(&mut writer);
+    server.sign_at(&mut writer, 0, TIME_SIGNED + 1);
+    assert_eq!(written(&writer), hex(RESPONSE));
+
+    let (parser, packet) = parse(&hex(RESPONSE));
+    assert_eq!(client.verify_at(&parser, &packet, TIME_SIGNED + 1), Ok(()));
+}
+
+#[test]
+fn changed_messages_and_old_signatures_are_rejected() {
+    // A flag changed after signing
+    let mut changed = hex(REQUEST);
+    changed[2] ^= 0x01;
+    let (parser, packet) = parse(&changed);
+    assert_eq!(TsigContext::new(key()).verify_at(&parser, &packet, TIME_SIGNED), Err(BADSIG));
+
+    // A response without the request MAC before it does not verify
+    let (parser, packet) = parse(&hex(RESPONSE));
+    assert_eq!(TsigContext::new(key()).verify_at(&parser, &packet, TIME_SIGNED + 1), Err(BADSIG));
+
+    // Outside the fudge of 300 seconds
+    let (parser, packet) = parse(&hex(REQUEST));
+    assert_eq!(TsigContext::new(key()).verify_at(&parser, &packet, TIME_SIGNED + 301), Err(BADTIME));
+    let (parser, packet) = parse(&hex(REQUEST));
+    assert_eq!(TsigContext::new(key()).verify_at(&parser, &packet, TIME_SIGNED + 300), Ok(()));
+}
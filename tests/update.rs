@@ -0,0 +1,197 @@
+// Dynamic updates (RFC 2136): prerequisites, all or nothing updates and the journal
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::{DNSPacket, DNSQuestion, DNSRecord, OpCode, QueryType, RCode};
+use rusty_twisted::parser::PacketParser;
+use rusty_twisted::server_config::ServerContext;
+use rusty_twisted::update::handle_update;
+use rusty_twisted::writer::PacketWriter;
+use rusty_twisted::zone::{Zone, ZoneConfig};
+
+const ZONE: &str = "
+$ORIGIN example.test.
+$TTL 300
+@       SOA   ns1 hostmaster 1 3600 600 86400 300
+        NS    ns1
+        NS    ns2
+ns1     A     192.0.2.1
+ns2     A     192.0.2.2
+www     A     192.0.2.80
+www     A     192.0.2.81
+alias   CNAME www
+";
+
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TYPE_ANY: u16 = 255;
+
+// A directory of its own for the master file and its journal, made again for every run
+fn zone_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rusty-twisted-update-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// A server with the zone loaded from its master file, updatable from 127.0.0.1
+fn context(dir: &Path) -> ServerContext {
+    let file = dir.join("example.test.zone");
+    fs::write(&file, ZONE).unwrap();
+
+    let mut context = ServerContext::new();
+    context.zones = vec![ZoneConfig {
+        origin: name("example.test"),
+        file: file.to_string_lossy().to_string(),
+        allow_update: vec!["127.0.0.1".to_string()],
+        allow_update_keys: Vec::new(),
+        signing: None,
+    }];
+    context.authority.load(&context.zones);
+    context
+}
+
+fn name(text: &str) -> Name {
+    Name::try_from(text).unwrap()
+}
+
+fn a(owner: &str, addr: &str, ttl: u32) -> DNSRecord {
+    DNSRecord::A { domain: name(owner), addr: addr.parse().unwrap(), ttl }
+}
+
+// Records of the classes ANY and NONE, as they come from the wire
+fn meta(owner: &str, qtype: u16, class: u16, data: Vec<u8>) -> DNSRecord {
+    DNSRecord::UNKNOWN { domain: name(owner), qtype, class, data, ttl: 0 }
+}
+
+// Send an UPDATE for example.test through the wire codec, from 127.0.0.1
+fn update(context: &ServerContext, prerequisites: Vec<DNSRecord>, updates: Vec<DNSRecord>) -> RCode {
+    let mut request = DNSPacket::new();
+    request.header.id = 0x2136;
+    request.header.opcode = OpCode::UPDATE;
+    request.questions.push(DNSQuestion { qname: name("example.test"), qtype: QueryType::SOA, class: 1 });
+    request.answers = prerequisites;
+    request.authorities = updates;
+
+    let mut writer = PacketWriter::new();
+    request.write_dns_packet(&mut writer);
+    let mut parser = PacketParser::from_bytes(&writer.buffer[..writer.position()]);
+    let request = DNSPacket::get_dns_packet(&mut parser).unwrap();
+
+    let peer: IpAddr = "127.0.0.1".parse().unwrap();
+    let response = handle_update(&request, peer, None, context);
+    assert_eq!(response.header.id, 0x2136);
+    assert_eq!(response.header.opcode, OpCode::UPDATE);
+    response.header.rcode
+}
+
+fn zone(context: &ServerContext) -> Zone {
+    context.authority.get_zone(&name("example.test")).unwrap()
+}
+
+fn count(zone: &Zone, owner: &str, qtype: QueryType) -> usize {
+    zone.get_records(&name(owner), qtype).len()
+}
+
+#[test]
+fn prerequisites_are_checked_before_any_change() {
+    let dir = zone_dir("prerequisites");
+    let context = context(&dir);
+    let add = || vec![a("new.example.test", "192.0.2.99", 300)];
+
+    // Name is in use, RRset exists, name is not in use, RRset does not exist
+    assert_eq!(update(&context, vec![meta("nope.example.test", TYPE_ANY, CLASS_ANY, vec![])], add()), RCode::NXDOMAIN);
+    assert_eq!(update(&context, vec![meta("www.example.test", 15, CLASS_ANY, vec![])], add()), RCode::NXRRSET);
+    assert_eq!(update(&context, vec![meta("www.example.test", TYPE_ANY, CLASS_NONE, vec![])], add()), RCode::YXDOMAIN);
+    assert_eq!(update(&context, vec![meta("www.example.test", 1, CLASS_NONE, vec![])], add()), RCode::YXRRSET);
+
+    // Value dependent: the whole RRset has to match, in any order and case
+    assert_eq!(update(&context, vec![a("www.example.test", "192.0.2.80", 0)], add()), RCode::NXRRSET);
+
+    // TTLs other than 0 and names outside the zone
+    assert_eq!(update(&context, vec![meta("www.example.test", 1, CLASS_ANY, vec![]), a("x.example.test", "192.0.2.1", 300)], add()), RCode::FORMERR);
+    assert_eq!(update(&context, vec![meta("www.other.test", 1, CLASS_ANY, vec![])], add()), RCode::NOTZONE);
+
+    // Nothing was changed by the failed requests
+    let unchanged = zone(&context);
+    assert_eq!(unchanged.get_serial(), Some(1));
+    assert_eq!(count(&unchanged, "new.example.test", QueryType::A), 0);
+
+    let prerequisites = vec![
+        meta("www.example.test", TYPE_ANY, CLASS_ANY, vec![]),
+        meta("new.example.test", TYPE_ANY, CLASS_NONE, vec![]),
+        a("WWW.example.test", "192.0.2.81", 0),
+        a("www.example.test", "192.0.2.80", 0),
+    ];
+    assert_eq!(update(&context, prerequisites, add()), RCode::NOERROR);
+    let updated = zone(&context);
+    assert_eq!(updated.get_serial(), Some(2));
+    assert_eq!(count(&updated, "new.example.test", QueryType::A), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_updates_leave_the_zone_as_it_was() {
+    let dir = zone_dir("rollback");
+    let mut context = context(&dir);
+
+    // A bad record after a good one: the good one is not applied either
+    let updates = vec![a("new.example.test", "192.0.2.99", 300), meta("www.example.test", 1, CLASS_ANY, vec![192, 0, 2, 80])];
+    assert_eq!(update(&context, vec![], updates), RCode::FORMERR);
+    assert_eq!(count(&zone(&context), "new.example.test", QueryType::A), 0);
+
+    // The SOA and the last NS of the origin stay, a CNAME does not join other data
+    let ns1 = DNSRecord::NS { domain: name("example.test"), host: name("ns1.example.test"), ttl: 0 }.get_rdata();
+    let ns2 = DNSRecord::NS { domain: name("example.test"), host: name("ns2.example.test"), ttl: 0 }.get_rdata();
+    let updates = vec![
+        meta("example.test", 6, CLASS_ANY, vec![]),
+        meta("example.test", 2, CLASS_NONE, ns1),
+        meta("example.test", 2, CLASS_NONE, ns2),
+        DNSRecord::CNAME { domain: name("ns1.example.test"), host: name("www.example.test"), ttl: 300 },
+    ];
+    assert_eq!(update(&context, vec![], updates), RCode::NOERROR);
+    let updated = zone(&context);
+    assert!(updated.get_soa().is_some());
+    assert_eq!(count(&updated, "example.test", QueryType::NS), 1);
+    assert_eq!(count(&updated, "ns1.example.test", QueryType::CNAME), 0);
+    assert_eq!(updated.get_serial(), Some(2));
+
+    // An update that cannot be written to the journal is not applied
+    context.zones[0].file = dir.join("missing").join("example.test.zone").to_string_lossy().to_string();
+    assert_eq!(update(&context, vec![], vec![a("new.example.test", "192.0.2.99", 300)]), RCode::SERVFAIL);
+    let unchanged = zone(&context);
+    assert_eq!(unchanged.get_serial(), Some(2));
+    assert_eq!(count(&unchanged, "new.example.test", QueryType::A), 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_journal_is_replayed_on_load() {
+    let dir = zone_dir("journal");
+    let context = context(&dir);
+    let config = context.zones[0].clone();
+
+    assert_eq!(update(&context, vec![], vec![a("new.example.test", "192.0.2.99", 300)]), RCode::NOERROR);
+    assert_eq!(update(&context, vec![], vec![meta("www.example.test", TYPE_ANY, CLASS_ANY, vec![])]), RCode::NOERROR);
+    // Changing nothing writes no journal entry
+    assert_eq!(update(&context, vec![], vec![meta("www.example.test", 1, CLASS_ANY, vec![])]), RCode::NOERROR);
+
+    let loaded = Zone::load(&config).unwrap();
+    assert_eq!(loaded.get_serial(), Some(3));
+    assert_eq!(count(&loaded, "new.example.test", QueryType::A), 1);
+    assert!(!loaded.name_exists(&name("www.example.test")));
+    assert_eq!(fs::read_to_string(format!("{}.jnl", config.file)).unwrap().lines().count(), 2);
+
+    // A master file newer than the journal is taken as it is
+    fs::write(&config.file, ZONE.replace("hostmaster 1 ", "hostmaster 5 ")).unwrap();
+    let loaded = Zone::load(&config).unwrap();
+    assert_eq!(loaded.get_serial(), Some(5));
+    assert_eq!(count(&loaded, "new.example.test", QueryType::A), 0);
+    assert_eq!(count(&loaded, "www.example.test", QueryType::A), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,304 @@
+// Round trips through the wire codec: parse(write(x)) == x
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+use rusty_twisted::name::{Name, NameError};
+use rusty_twisted::packet::{decode_type_bitmap, encode_type_bitmap, parse_reverse_name, reverse_name, DNSHeader, DNSPacket, DNSQuestion, DNSRecord, EdnsOption, OpCode, QueryType, RCode};
+use rusty_twisted::parser::{PacketParser, ParseError};
+use rusty_twisted::resolve_strategy::not_implemented;
+use rusty_twisted::writer::PacketWriter;
+
+// Types with their own DNSRecord variant; other types are kept as UNKNOWN
+const RECORD_TYPES: [u16; 17] = [1, 2, 5, 6, 12, 15, 16, 28, 33, 41, 43, 46, 47, 48, 50, 51, 250];
+
+// Labels of any bytes, dots included
+fn name() -> impl Strategy<Value = Name> {
+    vec(vec(any::<u8>(), 1..16), 0..5).prop_map(|labels| Name::from_labels(labels).unwrap())
+}
+
+fn bytes(max: usize) -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..max)
+}
+
+// Sorted and without duplicates, as decoded from a type bitmap
+fn types() -> impl Strategy<Value = Vec<u16>> {
+    vec(any::<u16>(), 0..10).prop_map(|mut types| {
+        types.sort();
+        types.dedup();
+        types
+    })
+}
+
+fn opcode() -> impl Strategy<Value = OpCode> {
+    (0u8..16).prop_map(OpCode::get_opcode)
+}
+
+// The header only holds the lower 4 bits of the RCODE
+fn rcode() -> impl Strategy<Value = RCode> {
+    (0u16..16).prop_map(RCode::get_rcode)
+}
+
+fn header() -> impl Strategy<Value = DNSHeader> {
+    (any::<u16>(), any::<[bool; 8]>(), opcode(), rcode(), any::<[u16; 4]>()).prop_map(|(id, flags, opcode, rcode, counts)| {
+        let mut header = DNSHeader::new();
+        header.id = id;
+        header.query = flags[0];
+        header.authoritative_answer = flags[1];
+        header.truncation = flags[2];
+        header.recursion_desired = flags[3];
+        header.recursion_available = flags[4];
+        header.zero = flags[5];
+        header.checking_disabled = flags[6];
+        header.authed_data = flags[7];
+        header.opcode = opcode;
+        header.rcode = rcode;
+        header.qd_count = counts[0];
+        header.an_count = counts[1];
+        header.ns_count = counts[2];
+        header.ar_count = counts[3];
+        header
+    })
+}
+
+fn question() -> impl Strategy<Value = DNSQuestion> {
+    (name(), any::<u16>(), any::<u16>()).prop_map(|(qname, qtype, class)| DNSQuestion {
+        qname,
+        qtype: QueryType::get_query_type(qtype),
+        class,
+    })
+}
+
+// Records of every variant, as the parser returns them
+fn record() -> impl Strategy<Value = DNSRecord> {
+    prop_oneof![
+        (name(), any::<u16>().prop_filter("own variant", |qtype| !RECORD_TYPES.contains(qtype)), any::<u16>(), bytes(64), any::<u32>())
+            .prop_map(|(domain, qtype, class, data, ttl)| DNSRecord::UNKNOWN { domain, qtype, class, data, ttl }),
+        (name(), any::<u32>(), any::<u32>())
+            .prop_map(|(domain, addr, ttl)| DNSRecord::A { domain, addr: Ipv4Addr::from(addr), ttl }),
+        (name(), name(), any::<u32>()).prop_map(|(domain, host, ttl)| DNSRecord::NS { domain, host, ttl }),
+        (name(), name(), any::<u32>()).prop_map(|(domain, host, ttl)| DNSRecord::CNAME { domain, host, ttl }),
+        (name(), name(), name(), any::<[u32; 5]>(), any::<u32>())
+            .prop_map(|(domain, mname, rname, [serial, refresh, retry, expire, minimum], ttl)| DNSRecord::SOA {
+                domain, mname, rname, serial, refresh, retry, expire, minimum, ttl,
+            }),
+        (name(), name(), any::<u32>()).prop_map(|(domain, host, ttl)| DNSRecord::PTR { domain, host, ttl }),
+        (name(), any::<u16>(), name(), any::<u32>())
+            .prop_map(|(domain, priority, host, ttl)| DNSRecord::MX { domain, priority, host, ttl }),
+        // Without any character-string the RDATA is empty, which parses as UNKNOWN
+        (name(), vec(bytes(256), 1..4), any::<u32>()).prop_map(|(domain, data, ttl)| DNSRecord::TXT { domain, data, ttl }),
+        (name(), any::<u128>(), any::<u32>())
+            .prop_map(|(domain, addr, ttl)| DNSRecord::AAAA { domain, addr: Ipv6Addr::from(addr), ttl }),
+        (name(), any::<[u16; 3]>(), name(), any::<u32>())
+            .prop_map(|(domain, [priority, weight, port], host, ttl)| DNSRecord::SRV { domain, priority, weight, port, host, ttl }),
+        (any::<u16>(), any::<u8>(), any::<u8>(), any::<bool>(), 0u16..0x8000, vec((any::<u16>(), bytes(32)), 0..4))
+            .prop_map(|(packet_len, ext_rcode, version, dnssec_ok, flags, options)| DNSRecord::OPT {
+                packet_len,
+                ext_rcode,
+                version,
+                dnssec_ok,
+                flags,
+                options: options.into_iter().map(|(code, data)| EdnsOption { code, data }).collect(),
+            }),
+        (name(), any::<u16>(), any::<u8>(), any::<u8>(), bytes(64), any::<u32>())
+            .prop_map(|(domain, key_tag, algorithm, digest_type, digest, ttl)| DNSRecord::DS { domain, key_tag, algorithm, digest_type, digest, ttl }),
+        (name(), any::<(u16, u8, u8)>(), any::<[u32; 3]>(), any::<u16>(), name(), bytes(128), any::<u32>())
+            .prop_map(|(domain, (type_covered, algorithm, labels), [original_ttl, expiration, inception], key_tag, signer, signature, ttl)| DNSRecord::RRSIG {
+                domain, type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature, ttl,
+            }),
+        (name(), name(), types(), any::<u32>()).prop_map(|(domain, next, types, ttl)| DNSRecord::NSEC { domain, next, types, ttl }),
+        (name(), any::<u16>(), any::<u8>(), any::<u8>(), bytes(128), any::<u32>())
+            .prop_map(|(domain, flags, protocol, algorithm, public_key, ttl)| DNSRecord::DNSKEY { domain, flags, protocol, algorithm, public_key, ttl }),
+        (name(), any::<(u8, u8, u16)>(), bytes(32), bytes(32), types(), any::<u32>())
+            .prop_map(|(domain, (hash_algorithm, flags, iterations), salt, next_hashed, types, ttl)| DNSRecord::NSEC3 {
+                domain, hash_algorithm, flags, iterations, salt, next_hashed, types, ttl,
+            }),
+        (name(), any::<(u8, u8, u16)>(), bytes(32), any::<u32>())
+            .prop_map(|(domain, (hash_algorithm, flags, iterations), salt, ttl)| DNSRecord::NSEC3PARAM { domain, hash_algorithm, flags, iterations, salt, ttl }),
+        (name(), name(), 0u64..1 << 48, any::<u16>(), bytes(64), any::<[u16; 2]>(), bytes(16))
+            .prop_map(|(domain, algorithm, time_signed, fudge, mac, [original_id, error], other)| DNSRecord::TSIG {
+                domain, algorithm, time_signed, fudge, mac, original_id, error, other,
+            }),
+    ]
+}
+
+fn parser_for(writer: &PacketWriter) -> PacketParser {
+    PacketParser::from_bytes(&writer.buffer[..writer.position()])
+}
+
+proptest! {
+    #[test]
+    fn header_round_trip(header in header()) {
+        let mut writer = PacketWriter::new();
+        header.write_header(&mut writer);
+
+        let mut parsed = DNSHeader::new();
+        let mut parser = parser_for(&writer);
+        parsed.parse_header(&mut parser).unwrap();
+        prop_assert_eq!(parser.position, parser.length);
+        prop_assert_eq!(parsed, header);
+    }
+
+    #[test]
+    fn question_round_trip(question in question()) {
+        let mut writer = PacketWriter::new();
+        question.write_question(&mut writer);
+
+        let mut parsed = DNSQuestion::new();
+        let mut parser = parser_for(&writer);
+        parsed.parse_question(&mut parser).unwrap();
+        prop_assert_eq!(parser.position, parser.length);
+        prop_assert_eq!(parsed, question);
+    }
+
+    #[test]
+    fn record_round_trip(record in record()) {
+        let mut writer = PacketWriter::new();
+        record.write_record(&mut writer);
+
+        let mut parser = parser_for(&writer);
+        let parsed = DNSRecord::parse_record(&mut parser).unwrap();
+        prop_assert_eq!(parser.position, parser.length);
+        prop_assert_eq!(parsed, record);
+    }
+
+    // Dots, backslashes and other bytes survive the text form
+    #[test]
+    fn name_text_round_trip(name in name()) {
+        let text = name.to_string();
+        let parsed: Name = text.parse().unwrap();
+        prop_assert_eq!(parsed.labels(), name.labels());
+        prop_assert_eq!(Name::try_from(text.as_str()), Ok(parsed));
+    }
+
+    #[test]
+    fn reverse_name_round_trip(addr in any::<IpAddr>()) {
+        prop_assert_eq!(parse_reverse_name(&reverse_name(addr)), Some(addr));
+    }
+
+    #[test]
+    fn type_bitmap_round_trip(types in types()) {
+        prop_assert_eq!(decode_type_bitmap(&encode_type_bitmap(&types)), Ok(types));
+    }
+
+    #[test]
+    fn packet_round_trip(
+        header in header(),
+        questions in vec(question(), 0..3),
+        answers in vec(record(), 0..4),
+        authorities in vec(record(), 0..4),
+        opt in option::of(record().prop_filter("OPT", |record| matches!(record, DNSRecord::OPT { .. }))),
+        compression in any::<bool>(),
+    ) {
+        // The extended RCODE of the OPT record is set from the header when writing
+        let answers: Vec<DNSRecord> = answers.into_iter().filter(|record| !matches!(record, DNSRecord::OPT { .. })).collect();
+        let authorities: Vec<DNSRecord> = authorities.into_iter().filter(|record| !matches!(record, DNSRecord::OPT { .. })).collect();
+        let resources: Vec<DNSRecord> = opt.into_iter().map(|mut record| {
+            if let DNSRecord::OPT { ref mut ext_rcode, .. } = record {
+                *ext_rcode = 0;
+            }
+            record
+        }).collect();
+
+        let mut packet = DNSPacket::new();
+        packet.header = header;
+        packet.questions = questions;
+        packet.answers = answers;
+        packet.authorities = authorities;
+        packet.resources = resources;
+        packet.compression = compression;
+
+        let mut writer = PacketWriter::new();
+        packet.write_dns_packet(&mut writer);
+
+        let mut parser = parser_for(&writer);
+        let parsed = DNSPacket::get_dns_packet(&mut parser).unwrap();
+        prop_assert_eq!(parser.position, parser.length);
+        prop_assert_eq!(parsed.header, packet.header);
+        prop_assert_eq!(parsed.questions, packet.questions);
+        prop_assert_eq!(parsed.answers, packet.answers);
+        prop_assert_eq!(parsed.authorities, packet.authorities);
+        prop_assert_eq!(parsed.resources, packet.resources);
+    }
+}
+
+// Window blocks that are empty, longer than 32 bytes, cut short or out of order
+#[test]
+fn type_bitmap_rejects_bad_blocks() {
+    let bad = |offset| Err(ParseError::BadTypeBitmap { offset });
+    assert_eq!(decode_type_bitmap(&[0, 0]), bad(0));
+    assert_eq!(decode_type_bitmap(&[[0, 33].as_slice(), &[0; 33]].concat()), bad(0));
+    assert_eq!(decode_type_bitmap(&[0, 1, 0x40, 1, 2, 0x80]), bad(3));
+    assert_eq!(decode_type_bitmap(&[0, 1, 0x40, 1]), bad(3));
+    assert_eq!(decode_type_bitmap(&[1, 1, 0x40, 0, 1, 0x40]), bad(3));
+    assert_eq!(decode_type_bitmap(&[0, 1, 0x40, 0, 1, 0x40]), bad(3));
+}
+
+// Labels over 63 bytes, names over 255 bytes and empty labels are not names
+#[test]
+fn names_over_the_limits_are_rejected() {
+    let label = "a".repeat(63);
+    assert!(Name::try_from(label.as_str()).is_ok());
+    assert_eq!(Name::try_from(format!("{}a.com", label)), Err(NameError::LabelTooLong(64)));
+
+    let long = [label.as_str(); 4].join(".");
+    assert_eq!(Name::try_from(long.as_str()), Err(NameError::NameTooLong(257)));
+    assert!(Name::try_from(&long[2..]).is_ok());
+
+    assert_eq!(Name::try_from("a..com"), Err(NameError::EmptyLabel));
+    assert_eq!(Name::try_from(".com"), Err(NameError::EmptyLabel));
+    assert!(serde_json::from_str::<Name>(&format!("\"{}a\"", label)).is_err());
+}
+
+// Only the classes ANY and NONE of UPDATE messages may have empty RDATA for any type
+#[test]
+fn empty_rdata_outside_update_classes_is_rejected() {
+    let record = |class: u16| {
+        let mut data = vec![1, b'a', 0, 0, 1];
+        data.extend_from_slice(&class.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        // A record after it, so the A RDATA can be read past its length
+        data.extend_from_slice(&[1, b'b', 0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        data
+    };
+
+    let parse = |data: &[u8]| DNSRecord::parse_record(&mut PacketParser::from_bytes(data));
+    assert_eq!(parse(&record(1)), Err(ParseError::BadRdLength { qtype: 1, rdlength: 0 }));
+    for class in [254, 255] {
+        assert!(matches!(parse(&record(class)), Ok(DNSRecord::UNKNOWN { qtype: 1, data, .. }) if data.is_empty()));
+    }
+}
+
+// Opcodes 3 and 6 to 15 keep their value and are answered with NOTIMP
+#[test]
+fn unknown_opcodes_get_notimp() {
+    for num in [3, 6, 15] {
+        let mut request = DNSPacket::new();
+        request.header.id = 0x1234;
+        request.header.opcode = OpCode::get_opcode(num);
+        assert_eq!(request.header.opcode, OpCode::UNKNOWN(num));
+
+        let response = not_implemented(&request);
+        assert_eq!(response.header.id, 0x1234);
+        assert_eq!(response.header.opcode.to_num(), num);
+        assert_eq!(response.header.rcode, RCode::NOTIMP);
+    }
+}
+
+// Reverse names with signs, several nibbles per label or missing labels are not addresses
+#[test]
+fn malformed_reverse_names_are_rejected() {
+    let parse = |text: &str| parse_reverse_name(&Name::try_from(text).unwrap());
+    assert_eq!(parse("1.2.0.192.IN-ADDR.ARPA."), Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+    assert_eq!(parse("+1.2.0.192.in-addr.arpa"), None);
+    assert_eq!(parse("1.2.0.256.in-addr.arpa"), None);
+    assert_eq!(parse("2.0.192.in-addr.arpa"), None);
+
+    let v6 = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+    assert_eq!(parse(v6), Some(IpAddr::V6("2001:db8::1".parse().unwrap())));
+    assert_eq!(parse(&v6.replacen("1.", "01.", 1)), None);
+    assert_eq!(parse(&v6.replacen("1.", "g.", 1)), None);
+    assert_eq!(parse(&v6[2..]), None);
+}
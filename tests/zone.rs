@@ -0,0 +1,103 @@
+// Wildcard synthesis in authoritative zones, with the example zone of RFC 4592 section 2.2.1
+use rusty_twisted::name::Name;
+use rusty_twisted::packet::{DNSPacket, DNSRecord, QueryType, RCode};
+use rusty_twisted::zone::Zone;
+use rusty_twisted::zone_file::ZoneFileParser;
+
+const ZONE: &str = "
+$ORIGIN example.
+$TTL 300
+@                 SOA   ns.example.com. hostmaster 1 3600 600 86400 300
+                  NS    ns.example.com.
+*                 TXT   \"this is a wildcard\"
+*                 MX    10 host1
+sub.*             TXT   \"this is not a wildcard\"
+host1             A     192.0.2.1
+_ssh._tcp.host1   SRV   0 0 22 host1
+_ssh._tcp.host2   SRV   0 0 22 host2
+subdel            NS    ns.example.com.
+";
+
+fn zone() -> Zone {
+    let origin = Name::try_from("example").unwrap();
+    let records = ZoneFileParser::parse_str(ZONE, &origin, "example.zone").unwrap();
+    Zone::from_records(&origin, records)
+}
+
+fn query(zone: &Zone, qname: &str, qtype: QueryType) -> DNSPacket {
+    zone.lookup(&Name::try_from(qname).unwrap(), qtype)
+}
+
+fn owners(records: &[DNSRecord]) -> Vec<String> {
+    records.iter().map(|record| record.clone().get_domain().unwrap_or_default().to_string()).collect()
+}
+
+fn txt(records: &[DNSRecord]) -> Vec<u8> {
+    match records {
+        [DNSRecord::TXT { data, .. }] => data.concat(),
+        _ => Vec::new(),
+    }
+}
+
+// NODATA: the name exists, but not with this type
+fn is_nodata(packet: &DNSPacket) -> bool {
+    packet.header.rcode == RCode::NOERROR && packet.answers.is_empty()
+        && matches!(packet.authorities.as_slice(), [DNSRecord::SOA { .. }])
+}
+
+fn is_nxdomain(packet: &DNSPacket) -> bool {
+    packet.header.rcode == RCode::NXDOMAIN && packet.answers.is_empty()
+}
+
+#[test]
+fn wildcards_answer_for_names_that_do_not_exist() {
+    let zone = zone();
+
+    // Synthesized with the query name as owner, one or more labels below the wildcard
+    let packet = query(&zone, "host3.example", QueryType::MX);
+    assert_eq!(packet.header.rcode, RCode::NOERROR);
+    assert_eq!(owners(&packet.answers), ["host3.example"]);
+    let packet = query(&zone, "foo.bar.example", QueryType::TXT);
+    assert_eq!(owners(&packet.answers), ["foo.bar.example"]);
+    assert_eq!(txt(&packet.answers), b"this is a wildcard");
+
+    // The wildcard exists, but not with this type
+    assert!(is_nodata(&query(&zone, "host3.example", QueryType::A)));
+
+    // Existing names are never matched by the wildcard
+    assert!(is_nodata(&query(&zone, "host1.example", QueryType::MX)));
+    assert!(is_nodata(&query(&zone, "sub.*.example", QueryType::MX)));
+}
+
+#[test]
+fn only_the_wildcard_at_the_closest_encloser_applies() {
+    let zone = zone();
+
+    // The closest encloser is host1.example, which has no wildcard below it
+    assert!(is_nxdomain(&query(&zone, "_telnet._tcp.host1.example", QueryType::SRV)));
+
+    // Empty non-terminals exist: they are answered with NODATA and block the wildcard above them
+    assert!(is_nodata(&query(&zone, "_tcp.host1.example", QueryType::TXT)));
+    assert!(is_nodata(&query(&zone, "host2.example", QueryType::MX)));
+    assert!(is_nxdomain(&query(&zone, "x._tcp.host2.example", QueryType::TXT)));
+
+    // Below a delegation the child zone answers
+    let packet = query(&zone, "host.subdel.example", QueryType::A);
+    assert!(!packet.header.authoritative_answer);
+    assert_eq!(owners(&packet.authorities), ["subdel.example"]);
+}
+
+#[test]
+fn a_literal_star_owner_is_an_ordinary_name() {
+    let zone = zone();
+
+    // Asked for directly, the wildcard answers with its own name
+    let packet = query(&zone, "*.example", QueryType::MX);
+    assert_eq!(owners(&packet.answers), ["*.example"]);
+
+    // Below the wildcard owner: sub.*.example exists, ghost.*.example does not
+    // and *.*.example is not a wildcard of the zone
+    let packet = query(&zone, "sub.*.example", QueryType::TXT);
+    assert_eq!(txt(&packet.answers), b"this is not a wildcard");
+    assert!(is_nxdomain(&query(&zone, "ghost.*.example", QueryType::MX)));
+}